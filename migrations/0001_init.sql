@@ -0,0 +1,27 @@
+-- Types are chosen so the same schema works on PostgreSQL and SQLite.
+CREATE TABLE raffle (
+    id                TEXT PRIMARY KEY,
+    title             TEXT             NOT NULL,
+    description       TEXT             NOT NULL,
+    status            TEXT             NOT NULL,
+    ticket_amount     BIGINT           NOT NULL,
+    ticket_price      DOUBLE PRECISION NOT NULL,
+    ticket_token_name TEXT             NOT NULL,
+    rule              TEXT             NOT NULL,
+    date_created      BIGINT           NOT NULL,
+    date_updated      BIGINT           NOT NULL
+);
+
+CREATE TABLE ticket (
+    id               TEXT PRIMARY KEY,
+    raffle_id        TEXT             NOT NULL,
+    username         TEXT             NOT NULL,
+    spl_tx_signature TEXT             NOT NULL,
+    amount_send      DOUBLE PRECISION NOT NULL,
+    amount           BIGINT           NOT NULL,
+    date_created     BIGINT           NOT NULL,
+    date_updated     BIGINT           NOT NULL
+);
+
+CREATE INDEX ticket_raffle_id ON ticket (raffle_id);
+CREATE INDEX ticket_spl_tx_signature ON ticket (spl_tx_signature);
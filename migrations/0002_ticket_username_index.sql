@@ -0,0 +1 @@
+CREATE INDEX ticket_username ON ticket (username);
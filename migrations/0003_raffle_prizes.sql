@@ -0,0 +1,2 @@
+-- JSON array of prize names in draw order.
+ALTER TABLE raffle ADD COLUMN prizes TEXT NOT NULL DEFAULT '[]';
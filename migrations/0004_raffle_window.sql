@@ -0,0 +1,3 @@
+-- Optional window (unix time) in which tickets are accepted.
+ALTER TABLE raffle ADD COLUMN date_start BIGINT;
+ALTER TABLE raffle ADD COLUMN date_end BIGINT;
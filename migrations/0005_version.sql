@@ -0,0 +1,3 @@
+-- Optimistic concurrency: incremented on every update.
+ALTER TABLE raffle ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE ticket ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- Responses of POST requests sent with an Idempotency-Key header.
+-- status, content_type and body stay NULL while the first request is running.
+CREATE TABLE idempotency (
+    idempotency_key TEXT PRIMARY KEY,
+    request_hash    TEXT   NOT NULL,
+    status          BIGINT,
+    content_type    TEXT,
+    body            TEXT,
+    date_created    BIGINT NOT NULL
+);
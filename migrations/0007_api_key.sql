@@ -0,0 +1,13 @@
+-- API keys, only the SHA-256 hash of the token is stored.
+CREATE TABLE api_key (
+    id             TEXT PRIMARY KEY,
+    name           TEXT   NOT NULL,
+    key_hash       TEXT   NOT NULL UNIQUE,
+    prefix         TEXT   NOT NULL,
+    -- JSON array of scope names
+    scopes         TEXT   NOT NULL,
+    date_created   BIGINT NOT NULL,
+    date_expires   BIGINT,
+    date_last_used BIGINT,
+    date_revoked   BIGINT
+);
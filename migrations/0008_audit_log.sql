@@ -0,0 +1,14 @@
+-- Append-only log of every create, update and delete made through the API.
+CREATE TABLE audit_log (
+    id           TEXT PRIMARY KEY,
+    actor        TEXT   NOT NULL,
+    action       TEXT   NOT NULL,
+    route        TEXT   NOT NULL,
+    collection   TEXT   NOT NULL,
+    document_id  TEXT   NOT NULL,
+    -- JSON array of {field, before, after}
+    changes      TEXT   NOT NULL,
+    date_created BIGINT NOT NULL
+);
+
+CREATE INDEX audit_log_document_id ON audit_log (document_id);
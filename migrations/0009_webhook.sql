@@ -0,0 +1,24 @@
+-- Webhook subscriptions and the log of every delivery attempt.
+CREATE TABLE webhook (
+    id           TEXT PRIMARY KEY,
+    url          TEXT   NOT NULL,
+    -- JSON array of event types
+    events       TEXT   NOT NULL,
+    secret       TEXT   NOT NULL,
+    date_created BIGINT NOT NULL
+);
+
+CREATE TABLE webhook_delivery (
+    id           TEXT PRIMARY KEY,
+    webhook_id   TEXT   NOT NULL,
+    event_id     TEXT   NOT NULL,
+    event        TEXT   NOT NULL,
+    attempt      BIGINT NOT NULL,
+    status       BIGINT,
+    error        TEXT,
+    -- 0 or 1
+    success      BIGINT NOT NULL,
+    date_created BIGINT NOT NULL
+);
+
+CREATE INDEX webhook_delivery_webhook_id ON webhook_delivery (webhook_id);
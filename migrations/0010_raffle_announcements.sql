@@ -0,0 +1,2 @@
+-- JSON object of Discord embed templates by event type.
+ALTER TABLE raffle ADD COLUMN announcements TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+-- JSON object of the ticket checks overriding the instance configuration.
+ALTER TABLE raffle ADD COLUMN validation TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,508 @@
+//! Typed async client for the Raffle API.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), raffle_client::Error> {
+//! use raffle_client::{Client, RaffleQuery};
+//!
+//! let client = Client::new("https://localhost:8080/api/v1", "<API_BEARER_TOKEN>")?;
+//! let page = client.list_raffles(&RaffleQuery::default()).await?;
+//! println!("{} raffles, next page: {:?}", page.items.len(), page.next_cursor);
+//! # Ok(())
+//! # }
+//! ```
+
+use futures::stream::{self, Stream, TryStreamExt};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use snafu::prelude::*;
+
+pub use raffle_model::*;
+
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Invalid base URL '{url}': {source}"))]
+    BaseUrl {
+        url: String,
+        source: url::ParseError,
+    },
+    #[snafu(display("Could not encode request body: {source}"))]
+    Encode { source: serde_json::Error },
+    #[snafu(display("Request failed: {source}"))]
+    Http { source: reqwest::Error },
+    /// The API rejected the request with a JSON [`ErrorBody`].
+    #[snafu(display("{status}: {} ({})", body.message, body.code))]
+    Api { status: StatusCode, body: ErrorBody },
+    /// The API answered with an error that has no JSON body, e.g. a rejected bearer token.
+    #[snafu(display("{status}: {body}"))]
+    Status { status: StatusCode, body: String },
+}
+
+impl Error {
+    /// Machine-readable code of an [`Error::Api`] response.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Api { body, .. } => Some(&body.code),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Api { status, .. } | Error::Status { status, .. } => Some(*status),
+            Error::Http { source } => source.status(),
+            Error::BaseUrl { .. } | Error::Encode { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+    token: String,
+}
+
+impl Client {
+    /// `base_url` includes the API prefix, e.g. `https://api:8080/api/v1`.
+    pub fn new(base_url: &str, token: &str) -> Result<Self, Error> {
+        Self::with_http_client(reqwest::Client::new(), base_url, token)
+    }
+
+    /// Uses a preconfigured `reqwest` client, e.g. one that trusts a self-signed certificate.
+    pub fn with_http_client(
+        http: reqwest::Client,
+        base_url: &str,
+        token: &str,
+    ) -> Result<Self, Error> {
+        let base_url = Url::parse(base_url).context(BaseUrlSnafu { url: base_url })?;
+        Ok(Self {
+            http,
+            base_url,
+            token: token.to_string(),
+        })
+    }
+
+    //region === POST ===
+    pub async fn add_raffle(&self, raffle: &Raffle) -> Result<String, Error> {
+        self.text(self.request(Method::POST, &["raffle"]).json(raffle))
+            .await
+    }
+
+    /// Submits a ticket; the response names the amount of tickets granted.
+    pub async fn add_ticket(&self, ticket: &Ticket) -> Result<String, Error> {
+        self.text(self.request(Method::POST, &["ticket"]).json(ticket))
+            .await
+    }
+
+    /// Like [`Client::add_raffle`], but safe to retry: the server replays the first
+    /// response for every request sent with the same `key`.
+    pub async fn add_raffle_idempotent(&self, raffle: &Raffle, key: &str) -> Result<String, Error> {
+        self.text(
+            self.request(Method::POST, &["raffle"])
+                .header(IDEMPOTENCY_KEY, key)
+                .json(raffle),
+        )
+        .await
+    }
+
+    /// Like [`Client::add_ticket`], but safe to retry with the same `key`.
+    pub async fn add_ticket_idempotent(&self, ticket: &Ticket, key: &str) -> Result<String, Error> {
+        self.text(
+            self.request(Method::POST, &["ticket"])
+                .header(IDEMPOTENCY_KEY, key)
+                .json(ticket),
+        )
+        .await
+    }
+    //endregion
+
+    //region === GET ===
+    pub async fn get_raffle(&self, id: ObjectId) -> Result<Option<Raffle>, Error> {
+        let mut raffles: Vec<Raffle> = self
+            .json(self.request(Method::GET, &["raffle", &id.to_hex()]))
+            .await?;
+        Ok(raffles.pop())
+    }
+
+    pub async fn get_ticket(&self, id: ObjectId) -> Result<Option<Ticket>, Error> {
+        let mut tickets: Vec<Ticket> = self
+            .json(self.request(Method::GET, &["ticket", &id.to_hex()]))
+            .await?;
+        Ok(tickets.pop())
+    }
+
+    pub async fn get_all_raffles(&self) -> Result<Vec<Raffle>, Error> {
+        self.json(self.request(Method::GET, &["raffle", "0"])).await
+    }
+
+    pub async fn get_all_tickets(&self) -> Result<Vec<Ticket>, Error> {
+        self.json(self.request(Method::GET, &["ticket", "0"])).await
+    }
+
+    pub async fn list_raffles(&self, query: &RaffleQuery) -> Result<Page<Raffle>, Error> {
+        self.json(self.request(Method::GET, &["raffles"]).query(query))
+            .await
+    }
+
+    pub async fn list_tickets(&self, query: &TicketQuery) -> Result<Page<Ticket>, Error> {
+        self.json(self.request(Method::GET, &["tickets"]).query(query))
+            .await
+    }
+
+    pub async fn raffle_tickets(
+        &self,
+        raffle_id: ObjectId,
+        query: &TicketQuery,
+    ) -> Result<Page<Ticket>, Error> {
+        self.json(
+            self.request(Method::GET, &["raffle", &raffle_id.to_hex(), "tickets"])
+                .query(query),
+        )
+        .await
+    }
+
+    /// Summary of a raffle listing up to `top` buyers (server default 10).
+    pub async fn raffle_stats(
+        &self,
+        raffle_id: ObjectId,
+        top: Option<usize>,
+    ) -> Result<RaffleStats, Error> {
+        let mut request = self.request(Method::GET, &["raffle", &raffle_id.to_hex(), "stats"]);
+        if let Some(top) = top {
+            request = request.query(&[("top", top)]);
+        }
+        self.json(request).await
+    }
+
+    pub async fn raffle_odds(
+        &self,
+        raffle_id: ObjectId,
+        username: &str,
+    ) -> Result<UserOdds, Error> {
+        self.json(self.request(
+            Method::GET,
+            &["raffle", &raffle_id.to_hex(), "odds", username],
+        ))
+        .await
+    }
+
+    pub async fn user_tickets(
+        &self,
+        username: &str,
+        query: &TicketQuery,
+    ) -> Result<Page<Ticket>, Error> {
+        self.json(
+            self.request(Method::GET, &["user", username, "tickets"])
+                .query(query),
+        )
+        .await
+    }
+
+    /// Every raffle matching `query`, fetched page by page starting at `query.cursor`.
+    pub fn raffles_stream(
+        &self,
+        query: RaffleQuery,
+    ) -> impl Stream<Item = Result<Raffle, Error>> + '_ {
+        paginate(query.cursor.clone(), move |cursor| {
+            let query = RaffleQuery {
+                cursor,
+                ..query.clone()
+            };
+            async move { self.list_raffles(&query).await }
+        })
+    }
+
+    /// Every ticket matching `query`, fetched page by page starting at `query.cursor`.
+    pub fn tickets_stream(
+        &self,
+        query: TicketQuery,
+    ) -> impl Stream<Item = Result<Ticket, Error>> + '_ {
+        paginate(query.cursor.clone(), move |cursor| {
+            let query = TicketQuery {
+                cursor,
+                ..query.clone()
+            };
+            async move { self.list_tickets(&query).await }
+        })
+    }
+    //endregion
+
+    //region == UPDATE ==
+    /// Applies an RFC 7396 merge patch, e.g. `json!({"status": "running", "version": 3})`.
+    /// With `version` set, fails with `version_conflict` if the raffle changed since.
+    /// Returns the number of matched raffles.
+    pub async fn update_raffle<P: Serialize + ?Sized>(
+        &self,
+        id: ObjectId,
+        patch: &P,
+    ) -> Result<u64, Error> {
+        self.merge_patch(&["raffle", &id.to_hex()], patch).await
+    }
+
+    /// Applies an RFC 7396 merge patch; only `username` is mutable.
+    /// Returns the number of matched tickets.
+    pub async fn update_ticket<P: Serialize + ?Sized>(
+        &self,
+        id: ObjectId,
+        patch: &P,
+    ) -> Result<u64, Error> {
+        self.merge_patch(&["ticket", &id.to_hex()], patch).await
+    }
+
+    async fn merge_patch<P: Serialize + ?Sized>(
+        &self,
+        segments: &[&str],
+        patch: &P,
+    ) -> Result<u64, Error> {
+        let body = serde_json::to_vec(patch).context(EncodeSnafu)?;
+        let matched = self
+            .text(
+                self.request(Method::PATCH, segments)
+                    .header(CONTENT_TYPE, "application/merge-patch+json")
+                    .body(body),
+            )
+            .await?;
+        Ok(matched.trim().parse().unwrap_or_default())
+    }
+    //endregion
+
+    //region === DELETE ===
+    pub async fn remove_raffle(&self, id: ObjectId) -> Result<(), Error> {
+        self.text(self.request(Method::DELETE, &["raffle", &id.to_hex()]))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_ticket(&self, id: ObjectId) -> Result<(), Error> {
+        self.text(self.request(Method::DELETE, &["ticket", &id.to_hex()]))
+            .await?;
+        Ok(())
+    }
+    //endregion
+
+    //region === API KEYS ===
+    /// Needs the `keys:admin` scope, like every key route.
+    pub async fn create_key(&self, key: &NewApiKey) -> Result<CreatedApiKey, Error> {
+        self.json(self.request(Method::POST, &["keys"]).json(key))
+            .await
+    }
+
+    pub async fn list_keys(&self) -> Result<Vec<ApiKeyInfo>, Error> {
+        self.json(self.request(Method::GET, &["keys"])).await
+    }
+
+    /// Issues a new token for the key; the old token stops working.
+    pub async fn rotate_key(&self, id: ObjectId) -> Result<CreatedApiKey, Error> {
+        self.json(self.request(Method::POST, &["keys", &id.to_hex(), "rotate"]))
+            .await
+    }
+
+    pub async fn revoke_key(&self, id: ObjectId) -> Result<(), Error> {
+        self.text(self.request(Method::DELETE, &["keys", &id.to_hex()]))
+            .await?;
+        Ok(())
+    }
+    //endregion
+
+    //region === WEBHOOKS ===
+    /// Registers a webhook; the returned secret signs its deliveries and is not shown again.
+    pub async fn create_webhook(&self, webhook: &NewWebhook) -> Result<CreatedWebhook, Error> {
+        self.json(self.request(Method::POST, &["webhooks"]).json(webhook))
+            .await
+    }
+
+    pub async fn list_webhooks(&self) -> Result<Vec<Webhook>, Error> {
+        self.json(self.request(Method::GET, &["webhooks"])).await
+    }
+
+    pub async fn remove_webhook(&self, id: ObjectId) -> Result<(), Error> {
+        self.text(self.request(Method::DELETE, &["webhooks", &id.to_hex()]))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn webhook_deliveries(
+        &self,
+        id: ObjectId,
+        query: &DeliveryQuery,
+    ) -> Result<Page<WebhookDelivery>, Error> {
+        self.json(
+            self.request(Method::GET, &["webhooks", &id.to_hex(), "deliveries"])
+                .query(query),
+        )
+        .await
+    }
+    //endregion
+
+    //region === AUDIT ===
+    pub async fn list_audit(&self, query: &AuditQuery) -> Result<Page<AuditEntry>, Error> {
+        self.json(self.request(Method::GET, &["audit"]).query(query))
+            .await
+    }
+    //endregion
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("http(s) URLs have a path")
+            .pop_if_empty()
+            .extend(segments);
+        self.http.request(method, url).bearer_auth(&self.token)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let response = request.send().await.context(HttpSnafu)?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.context(HttpSnafu)?;
+        match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(body) => ApiSnafu { status, body }.fail(),
+            Err(_) => StatusSnafu { status, body }.fail(),
+        }
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        self.send(request).await?.json().await.context(HttpSnafu)
+    }
+
+    async fn text(&self, request: RequestBuilder) -> Result<String, Error> {
+        self.send(request).await?.text().await.context(HttpSnafu)
+    }
+}
+
+/// Flattens the pages returned by `fetch` into a stream of items.
+fn paginate<'a, T, F, Fut>(
+    cursor: Option<String>,
+    fetch: F,
+) -> impl Stream<Item = Result<T, Error>> + 'a
+where
+    T: 'a,
+    F: Fn(Option<String>) -> Fut + 'a,
+    Fut: std::future::Future<Output = Result<Page<T>, Error>> + 'a,
+{
+    stream::try_unfold(Some(cursor), move |next| {
+        let page = next.map(&fetch);
+        async move {
+            match page {
+                Some(page) => {
+                    let page = page.await?;
+                    let next = page.next_cursor.map(Some);
+                    Ok(Some((stream::iter(page.items.into_iter().map(Ok)), next)))
+                }
+                None => Ok(None),
+            }
+        }
+    })
+    .try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    fn raffle(title: &str) -> Raffle {
+        Raffle {
+            id: ObjectId::new(),
+            title: title.to_string(),
+            description: String::new(),
+            status: "running".to_string(),
+            ticket_amount: 10,
+            ticket_price: 1.0,
+            ticket_token_name: "USDC".to_string(),
+            rule: String::new(),
+            prizes: vec![],
+            date_start: None,
+            date_end: None,
+            announcements: Default::default(),
+            validation: Default::default(),
+            date_created: 0,
+            date_updated: 0,
+            version: 0,
+        }
+    }
+
+    /// Serves three raffles one per page and rejects any token but `secret`.
+    async fn stub_server() -> String {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route(
+                    "/api/v1/raffles",
+                    web::get().to(
+                        |req: HttpRequest, query: web::Query<RaffleQuery>| async move {
+                            if req.headers().get("Authorization").map(|h| h.as_bytes())
+                                != Some(b"Bearer secret")
+                            {
+                                return HttpResponse::Unauthorized().finish();
+                            }
+                            let titles = ["a", "b", "c"];
+                            let index = query.cursor.as_deref().map_or(0, |c| c.parse().unwrap());
+                            let next_cursor =
+                                (index + 1 < titles.len()).then(|| (index + 1).to_string());
+                            HttpResponse::Ok().json(Page {
+                                items: vec![raffle(titles[index])],
+                                next_cursor,
+                            })
+                        },
+                    ),
+                )
+                .route(
+                    "/api/v1/raffle/{id}",
+                    web::get().to(|| async {
+                        HttpResponse::BadRequest().json(ErrorBody {
+                            code: "invalid_id".to_string(),
+                            message: "Invalid id".to_string(),
+                            violations: vec![],
+                        })
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}/api/v1", address)
+    }
+
+    #[actix_web::test]
+    async fn streams_every_page() {
+        let client = Client::new(&stub_server().await, "secret").unwrap();
+        let titles: Vec<String> = client
+            .raffles_stream(RaffleQuery::default())
+            .map_ok(|raffle| raffle.title)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(titles, ["a", "b", "c"]);
+    }
+
+    #[actix_web::test]
+    async fn maps_error_responses() {
+        let base_url = stub_server().await;
+        let unauthorized = Client::new(&base_url, "wrong")
+            .unwrap()
+            .list_raffles(&RaffleQuery::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(unauthorized, Error::Status { .. }));
+        assert_eq!(unauthorized.status(), Some(StatusCode::UNAUTHORIZED));
+
+        let invalid = Client::new(&base_url, "secret")
+            .unwrap()
+            .get_raffle(ObjectId::new())
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.status(), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(invalid.code(), Some("invalid_id"));
+    }
+}
@@ -0,0 +1,459 @@
+//! Request and response types of the Raffle API, shared by the server and `raffle_client`.
+//!
+//! The `openapi` feature derives the `utoipa` schemas the server publishes.
+
+use std::collections::BTreeMap;
+
+pub use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "openapi")]
+use utoipa::{IntoParams, ToSchema};
+
+/// JSON form of an `ObjectId`, used for the OpenAPI schema only.
+#[cfg(feature = "openapi")]
+#[derive(Deserialize, Serialize, ToSchema)]
+#[schema(as = ObjectId)]
+pub struct ObjectIdSchema {
+    /// 24 hex characters
+    #[serde(rename = "$oid")]
+    pub oid: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Raffle {
+    #[serde(default)]
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub status: String,
+    pub ticket_amount: u16,
+    pub ticket_price: f32,
+    pub ticket_token_name: String,
+    #[serde(default)]
+    pub rule: String,
+    /// Prizes in draw order; a raffle without entries awards a single prize.
+    #[serde(default)]
+    pub prizes: Vec<String>,
+    /// Unix time tickets are accepted from, if limited.
+    #[serde(default)]
+    pub date_start: Option<i64>,
+    /// Unix time tickets are accepted until, if limited.
+    #[serde(default)]
+    pub date_end: Option<i64>,
+    /// Discord embeds by event type (`raffle.opened`, `ticket.created`, ...), replacing the
+    /// built-in announcement of that event.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub announcements: BTreeMap<String, EmbedTemplate>,
+    /// Ticket checks of this raffle; unset ones follow the instance configuration.
+    #[serde(default, skip_serializing_if = "ValidationPolicy::is_empty")]
+    pub validation: ValidationPolicy,
+    #[serde(default)]
+    pub date_created: i64,
+    #[serde(default)]
+    pub date_updated: i64,
+    /// Assigned by the server: 0 on insert, incremented on every update, also sent as `ETag`.
+    #[serde(default)]
+    pub version: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Ticket {
+    #[serde(default)]
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub raffle_id: ObjectId,
+    pub username: String,
+    pub spl_tx_signature: String,
+    #[serde(default)]
+    pub amount_send: f32,
+    #[serde(default)]
+    pub amount: u16,
+    #[serde(default)]
+    pub date_created: i64,
+    #[serde(default)]
+    pub date_updated: i64,
+    /// Assigned by the server: 0 on insert, incremented on every update, also sent as `ETag`.
+    #[serde(default)]
+    pub version: i64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query string of `GET /raffles`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(IntoParams), into_params(parameter_in = Query))]
+pub struct RaffleQuery {
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
+    pub status: Option<String>,
+    pub token: Option<String>,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+/// Query string of `GET /tickets`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(IntoParams), into_params(parameter_in = Query))]
+pub struct TicketQuery {
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
+    pub raffle_id: Option<String>,
+    pub username: Option<String>,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+/// One page of a list endpoint. Pass `next_cursor` as `cursor` to fetch the next page.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Buyer {
+    pub username: String,
+    pub tickets: u32,
+}
+
+/// Summary returned by `GET /raffle/{id}/stats`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct RaffleStats {
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub raffle_id: ObjectId,
+    pub tickets_total: u32,
+    pub tickets_sold: u32,
+    pub tickets_remaining: u32,
+    pub participants: u32,
+    /// Sum of `amount_send` keyed by token name.
+    pub revenue: BTreeMap<String, f32>,
+    /// Participants with the most tickets, largest first.
+    pub top_buyers: Vec<Buyer>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct PrizeOdds {
+    pub prize: String,
+    pub probability: f64,
+}
+
+/// A user's chances in a raffle, returned by `GET /raffle/{id}/odds/{username}`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct UserOdds {
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub raffle_id: ObjectId,
+    pub username: String,
+    pub tickets: u32,
+    pub tickets_sold: u32,
+    /// `tickets / tickets_sold`, 0 when nothing is sold yet.
+    pub share: f64,
+    pub prizes: Vec<PrizeOdds>,
+    /// Probability of winning at least one prize.
+    pub any_prize: f64,
+}
+
+/// JSON body of every error response.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ErrorBody {
+    /// Stable, machine-readable error code such as `raffle_not_found`.
+    pub code: String,
+    pub message: String,
+    /// Every rule a request payload broke, for `invalid_raffle`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<Violation>,
+}
+
+/// A field of a request payload that broke a validation rule.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+/// An API key as listed by `GET /keys`; the token itself is only returned once.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ApiKeyInfo {
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    pub name: String,
+    /// `read`, `ticket:submit`, `raffle:admin`, `keys:admin`, `audit:read` or `webhooks:admin`
+    pub scopes: Vec<String>,
+    /// First characters of the token, to tell keys apart.
+    pub prefix: String,
+    pub date_created: i64,
+    #[serde(default)]
+    pub date_expires: Option<i64>,
+    #[serde(default)]
+    pub date_last_used: Option<i64>,
+    #[serde(default)]
+    pub date_revoked: Option<i64>,
+}
+
+/// Request body of `POST /keys`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Unix time after which the key is rejected.
+    #[serde(default)]
+    pub date_expires: Option<i64>,
+}
+
+/// Response of `POST /keys` and `POST /keys/{id}/rotate`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct CreatedApiKey {
+    pub key: ApiKeyInfo,
+    /// Bearer token; it is not stored and cannot be shown again.
+    pub token: String,
+}
+
+/// A create, update or delete recorded in the audit log, see `GET /audit`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct AuditEntry {
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    /// Name of the API key that made the change.
+    pub actor: String,
+    /// `create`, `update` or `delete`
+    pub action: String,
+    /// Method and route pattern, e.g. `PATCH /raffle/{id}`.
+    pub route: String,
+    /// `raffle`, `ticket`, `api_key` or `webhook`
+    pub collection: String,
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub document_id: ObjectId,
+    /// Top-level fields that changed, `null` on the missing side of a create or delete.
+    pub changes: Vec<AuditChange>,
+    pub date_created: i64,
+}
+
+/// One field of an [`AuditEntry`].
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct AuditChange {
+    pub field: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+/// Query string of `GET /audit`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(IntoParams), into_params(parameter_in = Query))]
+pub struct AuditQuery {
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub collection: Option<String>,
+    pub document_id: Option<String>,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+/// A Discord embed; `{raffle.<field>}` and, for ticket events, `{ticket.<field>}` are replaced
+/// with the values of the raffle and the ticket.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct EmbedTemplate {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    /// RGB color of the embed border, e.g. `5793266`.
+    #[serde(default)]
+    pub color: Option<u32>,
+}
+
+/// Checks a raffle applies to submitted tickets, each overriding the instance default when set.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct ValidationPolicy {
+    /// The transferred token must be the raffle's `ticket_token_name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_token_symbol: Option<bool>,
+    /// The transaction must have succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_tx_status: Option<bool>,
+    /// The raffle must be `running`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_raffle_running: Option<bool>,
+    /// The transfer must lie inside `date_start`..`date_end`, or after the raffle was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_raffle_time: Option<bool>,
+    /// The transfer must go to `sol_wallet`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_raffle_destination: Option<bool>,
+    /// The transaction must not have bought tickets before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_raffle_used_signature: Option<bool>,
+    /// Wallet ticket payments must be sent to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sol_wallet: Option<String>,
+}
+
+impl ValidationPolicy {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Something that happened to a raffle, delivered to webhooks and `GET /events`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Event {
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub raffle_id: ObjectId,
+    /// The ticket for `ticket.*` events, the raffle otherwise.
+    pub data: serde_json::Value,
+    pub date_created: i64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub enum EventKind {
+    #[serde(rename = "ticket.created")]
+    TicketCreated,
+    /// The status was set to `running`.
+    #[serde(rename = "raffle.opened")]
+    RaffleOpened,
+    /// The last ticket was sold, the raffle is closed.
+    #[serde(rename = "raffle.sold_out")]
+    RaffleSoldOut,
+    /// The status was set to `closed`.
+    #[serde(rename = "raffle.closed")]
+    RaffleClosed,
+    /// The status was set to `drawn`.
+    #[serde(rename = "raffle.drawn")]
+    RaffleDrawn,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 5] = [
+        EventKind::TicketCreated,
+        EventKind::RaffleOpened,
+        EventKind::RaffleSoldOut,
+        EventKind::RaffleClosed,
+        EventKind::RaffleDrawn,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::TicketCreated => "ticket.created",
+            EventKind::RaffleOpened => "raffle.opened",
+            EventKind::RaffleSoldOut => "raffle.sold_out",
+            EventKind::RaffleClosed => "raffle.closed",
+            EventKind::RaffleDrawn => "raffle.drawn",
+        }
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+            .ok_or_else(|| format!("unknown event type '{}'", value))
+    }
+}
+
+/// A registered webhook as listed by `GET /webhooks`; the signing secret is only returned once.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Webhook {
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    pub url: String,
+    /// Event types delivered to `url`.
+    pub events: Vec<EventKind>,
+    pub date_created: i64,
+}
+
+/// Request body of `POST /webhooks`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct NewWebhook {
+    pub url: String,
+    pub events: Vec<EventKind>,
+}
+
+/// Response of `POST /webhooks`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct CreatedWebhook {
+    pub webhook: Webhook,
+    /// HMAC-SHA256 key of the `X-Raffle-Signature` header; it cannot be shown again.
+    pub secret: String,
+}
+
+/// One attempt to deliver an event to a webhook.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id")]
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub id: ObjectId,
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub webhook_id: ObjectId,
+    #[cfg_attr(feature = "openapi", schema(value_type = ObjectIdSchema))]
+    pub event_id: ObjectId,
+    pub event: EventKind,
+    /// 1 for the first attempt.
+    pub attempt: u32,
+    /// HTTP status of the response, `None` if none was received.
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub success: bool,
+    pub date_created: i64,
+}
+
+/// Query string of `GET /webhooks/{id}/deliveries`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(IntoParams), into_params(parameter_in = Query))]
+pub struct DeliveryQuery {
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
@@ -1,199 +1,1169 @@
-use crate::{validator, DatabaseRaffle, ObjectId};
-use actix_web::{delete, get, patch, post, web, HttpResponse};
-use log::{error, info};
-use mongodb::{Client};
+use crate::auth::{ApiKey, Scope};
+use crate::error::{ApiError, InvalidIdSnafu, VersionConflictSnafu};
+use crate::repository::{PageRequest, RaffleFilter, RaffleRepository, TicketFilter};
+use crate::settings::Settings;
+use crate::events::{self, EventBus};
+use crate::{audit, idempotency, merge_patch, stats, validator, ObjectId};
+use actix_web::http::header::{EntityTag, IfMatch, ETAG};
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use log::info;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use snafu::prelude::*;
 use super::model::*;
 
 //region === POST ===
+#[utoipa::path(
+    tag = "raffle",
+    params(IdempotencyKey),
+    request_body = Raffle,
+    responses(
+        (status = 200, description = "Raffle created", body = String, example = "ok"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 409, description = "Request with this key still in progress", body = ErrorBody),
+        (status = 422, description = "Raffle breaks validation rules or key reused", body = ErrorBody),
+    ),
+)]
 #[post("/raffle")]
 pub async fn add_raffle(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
-    form: web::Json<Raffle>,
-) -> HttpResponse {
-    let mut data = form.into_inner();
-    let result = db_interface.insert_raffle(&client, &mut data).await;
-    match result {
-        Ok(_) => {
-            info!("{:?}", data);
-            HttpResponse::Ok().body("ok")
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    event_bus: web::Data<EventBus>,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    form: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::RaffleAdmin)?;
+    let body = serde_json::to_vec(&*form).unwrap_or_default();
+    let mut data: Raffle = parse_body(form.into_inner())?;
+    idempotency::run(db_interface.as_ref(), settings.idempotency.ttl_secs, &key, &req, &body, || async {
+        validator::check_raffle(&data, &settings.validation)?;
+        db_interface.insert_raffle(&mut data).await?;
+        audit::record(db_interface.as_ref(), &key, &req, "raffle", data.id, None, Some(&data)).await;
+        // `insert_raffle` keeps a `running` status, such a raffle is opened right away
+        if data.status == "running" {
+            event_bus.publish(EventKind::RaffleOpened, data.id, &data);
         }
-        Err(err) => {
-            error!("{:?}", data);
-            HttpResponse::InternalServerError().body(format!("{:?}", err))
-        }
-    }
+        info!("{:?}", data);
+        Ok(HttpResponse::Ok().body("ok"))
+    })
+    .await
 }
 
+#[utoipa::path(
+    tag = "ticket",
+    params(IdempotencyKey),
+    request_body(content = Ticket, description = "`raffle_id`, `username` and `spl_tx_signature` are required"),
+    responses(
+        (status = 200, description = "Ticket stored", body = String, example = "You got 3 Tickets"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Raffle does not exist", body = ErrorBody),
+        (status = 409, description = "Raffle not running, signature used, sold out or request in progress", body = ErrorBody),
+        (status = 422, description = "Transaction does not match the raffle or key reused", body = ErrorBody),
+        (status = 429, description = "Too many tickets for this key or username", body = ErrorBody),
+        (status = 502, description = "Solscan request failed", body = ErrorBody),
+    ),
+)]
 #[post("/ticket")]
 pub async fn add_ticket(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
-    form: web::Json<Ticket>,
-) -> HttpResponse {
-    let mut ticket = form.into_inner();
-    HttpResponse::Ok().body("hello there");
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    event_bus: web::Data<EventBus>,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    form: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::TicketSubmit)?;
+    // The raw JSON is hashed, defaults like a fresh `_id` would differ between retries
+    let body = serde_json::to_vec(&*form).unwrap_or_default();
+    let mut ticket: Ticket = parse_body(form.into_inner())?;
     info!("{:?}", ticket);
 
-    match validator::validate_ticket(&client, &db_interface, ticket.clone()).await {
-        Ok(tickets) => {
-            ticket.amount = tickets;
-            let result = db_interface.insert_ticket(&client, &mut ticket).await;
-
-            match result {
-                Ok(_) => {
-                    info!("{:?}", ticket);
-                    HttpResponse::Ok().body(format!("You got {} Tickets", ticket.amount))
-                }
-                Err(err) => {
-                    error!("{:?}", err);
-                    HttpResponse::Ok().body(err.to_string())
-                }
-            }
+    idempotency::run(db_interface.as_ref(), settings.idempotency.ttl_secs, &key, &req, &body, || async {
+        let db_interface = db_interface.as_ref();
+        store_ticket(db_interface, &event_bus, &settings, &key, &req, &mut ticket).await?;
+        Ok(HttpResponse::Ok().body(format!("You got {} Tickets", ticket.amount)))
+    })
+    .await
+}
+
+/// Validates and stores `ticket` on behalf of `key`, setting its amount, and announces it.
+pub async fn store_ticket(
+    db_interface: &dyn RaffleRepository,
+    event_bus: &EventBus,
+    settings: &Settings,
+    key: &ApiKey,
+    req: &HttpRequest,
+    ticket: &mut Ticket,
+) -> Result<(), ApiError> {
+    ticket.amount = validator::validate_ticket(db_interface, settings, ticket).await?;
+    let closed = db_interface.allocate_ticket(ticket).await?;
+    if ticket.amount == 0 {
+        return Err(validator::Error::ZeroTickets.into());
+    }
+    audit::record(db_interface, key, req, "ticket", ticket.id, None, Some(&*ticket)).await;
+    event_bus.publish(EventKind::TicketCreated, ticket.raffle_id, &*ticket);
+    // Only the allocation that took the last ticket announces the sell-out
+    if closed {
+        if let Some(raffle) = db_interface.get_raffle_by_id(ticket.raffle_id).await?.pop() {
+            event_bus.publish(EventKind::RaffleSoldOut, raffle.id, &raffle);
         }
-        Err(err) => HttpResponse::Ok().body(err.to_string()),
     }
+    info!("{:?}", ticket);
+    Ok(())
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Result<T, ApiError> {
+    serde_json::from_value(value).map_err(|err| ApiError::InvalidRequest {
+        message: format!("Json deserialize error: {}", err),
+    })
+}
+
+/// `Idempotency-Key` header of the POST routes.
+#[derive(IntoParams)]
+#[into_params(parameter_in = Header)]
+#[allow(dead_code)]
+struct IdempotencyKey {
+    /// Retries with the same key replay the first response instead of running again.
+    #[param(rename = "Idempotency-Key")]
+    idempotency_key: Option<String>,
 }
 //endregion
 
 //region === GET ===
+#[utoipa::path(
+    tag = "raffle",
+    params(("id" = String, Path, description = "Raffle id, or `0` for all raffles")),
+    responses(
+        (status = 200, description = "Matching raffles", body = Vec<Raffle>),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+    ),
+)]
 #[get("/raffle/{id}")]
 pub async fn get_raffle(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
     id: web::Path<String>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
     let oid = id.into_inner();
     let result = match oid.as_str() {
-        "0" => db_interface.get_all_raffles(&client).await,
+        "0" => db_interface.get_all_raffles().await?,
         _ => {
-            let data = ObjectId::parse_str(oid.as_str()).unwrap();
-            db_interface.get_raffle_by_id(&client, data).await
+            let data = ObjectId::parse_str(oid.as_str()).context(InvalidIdSnafu)?;
+            db_interface.get_raffle_by_id(data).await?
         }
     };
-    match result {
-        Ok(_) => {
-            info!("{:?}", result);
-            HttpResponse::Ok().json(result.unwrap())
-        }
-        Err(err) => {
-            error!("{:?}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
-    }
+    info!("{:?}", result);
+    Ok(with_etag(HttpResponse::Ok(), &result, |raffle| raffle.version).json(result))
+}
+
+#[utoipa::path(
+    tag = "raffle",
+    params(("id" = String, Path, description = "Raffle id"), TicketQuery),
+    responses(
+        (status = 200, description = "Tickets of the raffle", body = Page<Ticket>),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+        (status = 404, description = "Raffle does not exist", body = ErrorBody),
+    ),
+)]
+#[get("/raffle/{id}/tickets")]
+pub async fn get_raffle_tickets(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    id: web::Path<String>,
+    query: web::Query<TicketQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let query = query.into_inner();
+    let raffle = find_raffle(db_interface.as_ref(), &id).await?;
+    let cursor = parse_optional_id(query.cursor.as_deref())?;
+    let raffle_id = raffle.id;
+    let filter = TicketFilter {
+        raffle_id: Some(raffle_id),
+        username: query.username,
+        date_from: query.date_from,
+        date_to: query.date_to,
+    };
+    let page = PageRequest::new(query.limit, cursor, query.sort);
+    Ok(HttpResponse::Ok().json(db_interface.find_tickets(&filter, page).await?))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsQuery {
+    /// Number of top buyers to list, default 10
+    top: Option<usize>,
+}
+
+#[utoipa::path(
+    tag = "raffle",
+    params(("id" = String, Path, description = "Raffle id"), StatsQuery),
+    responses(
+        (status = 200, description = "Raffle summary", body = RaffleStats),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+        (status = 404, description = "Raffle does not exist", body = ErrorBody),
+    ),
+)]
+#[get("/raffle/{id}/stats")]
+pub async fn get_raffle_stats(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    id: web::Path<String>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let raffle = find_raffle(db_interface.as_ref(), &id).await?;
+    let tickets = db_interface.get_tickets_by_id_raffle(raffle.id).await?;
+    Ok(HttpResponse::Ok().json(stats::raffle_stats(&raffle, &tickets, query.top.unwrap_or(10))))
+}
+
+#[utoipa::path(
+    tag = "raffle",
+    params(
+        ("id" = String, Path, description = "Raffle id"),
+        ("username" = String, Path, description = "Participant"),
+    ),
+    responses(
+        (status = 200, description = "Chances of the user", body = UserOdds),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+        (status = 404, description = "Raffle does not exist", body = ErrorBody),
+    ),
+)]
+#[get("/raffle/{id}/odds/{username}")]
+pub async fn get_raffle_odds(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let (id, username) = path.into_inner();
+    let raffle = find_raffle(db_interface.as_ref(), &id).await?;
+    let tickets = db_interface.get_tickets_by_id_raffle(raffle.id).await?;
+    Ok(HttpResponse::Ok().json(stats::user_odds(&raffle, &tickets, &username)))
+}
+
+#[utoipa::path(
+    tag = "ticket",
+    params(("username" = String, Path, description = "Participant"), TicketQuery),
+    responses(
+        (status = 200, description = "Tickets of the user", body = Page<Ticket>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    ),
+)]
+#[get("/user/{username}/tickets")]
+pub async fn get_user_tickets(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    username: web::Path<String>,
+    query: web::Query<TicketQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let query = query.into_inner();
+    let cursor = parse_optional_id(query.cursor.as_deref())?;
+    let raffle_id = parse_optional_id(query.raffle_id.as_deref())?;
+    let filter = TicketFilter {
+        raffle_id,
+        username: Some(username.into_inner()),
+        date_from: query.date_from,
+        date_to: query.date_to,
+    };
+    let page = PageRequest::new(query.limit, cursor, query.sort);
+    Ok(HttpResponse::Ok().json(db_interface.find_tickets(&filter, page).await?))
+}
+
+#[utoipa::path(
+    tag = "raffle",
+    params(RaffleQuery),
+    responses(
+        (status = 200, description = "One page of raffles", body = Page<Raffle>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    ),
+)]
+#[get("/raffles")]
+pub async fn list_raffles(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    query: web::Query<RaffleQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let query = query.into_inner();
+    let cursor = parse_optional_id(query.cursor.as_deref())?;
+    let filter = RaffleFilter {
+        status: query.status,
+        token: query.token,
+        date_from: query.date_from,
+        date_to: query.date_to,
+    };
+    let page = PageRequest::new(query.limit, cursor, query.sort);
+    Ok(HttpResponse::Ok().json(db_interface.find_raffles(&filter, page).await?))
+}
+
+#[utoipa::path(
+    tag = "ticket",
+    params(TicketQuery),
+    responses(
+        (status = 200, description = "One page of tickets", body = Page<Ticket>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    ),
+)]
+#[get("/tickets")]
+pub async fn list_tickets(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    query: web::Query<TicketQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let query = query.into_inner();
+    let cursor = parse_optional_id(query.cursor.as_deref())?;
+    let raffle_id = parse_optional_id(query.raffle_id.as_deref())?;
+    let filter = TicketFilter {
+        raffle_id,
+        username: query.username,
+        date_from: query.date_from,
+        date_to: query.date_to,
+    };
+    let page = PageRequest::new(query.limit, cursor, query.sort);
+    Ok(HttpResponse::Ok().json(db_interface.find_tickets(&filter, page).await?))
 }
 
+fn parse_optional_id(value: Option<&str>) -> Result<Option<ObjectId>, ApiError> {
+    value
+        .map(ObjectId::parse_str)
+        .transpose()
+        .context(InvalidIdSnafu)
+}
+
+async fn find_raffle(db_interface: &dyn RaffleRepository, id: &str) -> Result<Raffle, ApiError> {
+    let raffle_id = ObjectId::parse_str(id).context(InvalidIdSnafu)?;
+    db_interface
+        .get_raffle_by_id(raffle_id)
+        .await?
+        .pop()
+        .ok_or(ApiError::RaffleNotFound)
+}
+
+#[utoipa::path(
+    tag = "ticket",
+    params(("id" = String, Path, description = "Ticket id, or `0` for all tickets")),
+    responses(
+        (status = 200, description = "Matching tickets", body = Vec<Ticket>),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+    ),
+)]
 #[get("/ticket/{id}")]
 pub async fn get_ticket(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
     id: web::Path<String>,
-) -> HttpResponse {
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
     let oid = id.into_inner();
     let result = match oid.as_str() {
-        "0" => db_interface.get_all_tickets(&client).await,
+        "0" => db_interface.get_all_tickets().await?,
         _ => {
-            let data = ObjectId::parse_str(oid.as_str()).unwrap();
-            db_interface.get_ticket_by_id(&client, data).await
+            let data = ObjectId::parse_str(oid.as_str()).context(InvalidIdSnafu)?;
+            db_interface.get_ticket_by_id(data).await?
         }
     };
-    match result {
-        Ok(_) => {
-            info!("{:?}", result);
-            HttpResponse::Ok().json(result.unwrap())
-        }
-        Err(err) => {
-            error!("{:?}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
-    }
+    info!("{:?}", result);
+    Ok(with_etag(HttpResponse::Ok(), &result, |ticket| ticket.version).json(result))
 }
 //endregion
 
 //region == UPDATE ==
+#[utoipa::path(
+    tag = "raffle",
+    params(
+        ("id" = String, Path, description = "Raffle id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` of the raffle as read"),
+    ),
+    request_body(
+        content = Object,
+        content_type = "application/merge-patch+json",
+        description = "RFC 7396 merge patch of a `Raffle`; `_id`, `date_created` and `date_updated` are immutable, \
+            `version` must match the stored one",
+    ),
+    responses(
+        (status = 200, description = "Number of matched raffles", body = String, example = "1"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Raffle does not exist", body = ErrorBody),
+        (status = 409, description = "Raffle changed since it was read", body = ErrorBody),
+        (status = 422, description = "Patch names unknown or immutable fields or breaks validation rules", body = ErrorBody),
+    ),
+)]
 #[patch("/raffle/{id}")]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_raffle(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    event_bus: web::Data<EventBus>,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
     id: web::Path<String>,
-    form: web::Json<Raffle>,
-) -> HttpResponse {
-    let mut data = form.into_inner();
-    data.id = ObjectId::parse_str(id.into_inner().to_string()).unwrap();
-    let result = db_interface.update_raffle(&client, &mut data).await;
-    match result {
-        Ok(result) => {
-            info!("Updated {:?}", data);
-            HttpResponse::Ok().body(format!("{:?}", result.matched_count))
-        }
-        Err(err) => {
-            error!("{:?}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
+    if_match: Option<web::Header<IfMatch>>,
+    patch: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::RaffleAdmin)?;
+    let raffle = find_raffle(db_interface.as_ref(), &id).await?;
+    let mut patch = patch.into_inner();
+    check_version(if_match, &mut patch, raffle.version)?;
+    let mut data = merge_patch::apply(&raffle, &patch, RAFFLE_FIELDS, RAFFLE_IMMUTABLE)?;
+    validator::check_raffle(&data, &settings.validation)?;
+    let result = db_interface.update_raffle(&mut data).await?;
+    ensure!(result > 0, VersionConflictSnafu);
+    audit::record(db_interface.as_ref(), &key, &req, "raffle", data.id, Some(&raffle), Some(&data)).await;
+    if let Some(kind) = events::status_change(&raffle, &data) {
+        event_bus.publish(kind, data.id, &data);
     }
+    info!("Updated {:?}", data);
+    Ok(HttpResponse::Ok()
+        .insert_header((ETAG, etag(data.version)))
+        .body(format!("{:?}", result)))
 }
 
+#[utoipa::path(
+    tag = "ticket",
+    params(
+        ("id" = String, Path, description = "Ticket id"),
+        ("If-Match" = Option<String>, Header, description = "`ETag` of the ticket as read"),
+    ),
+    request_body(
+        content = Object,
+        content_type = "application/merge-patch+json",
+        description = "RFC 7396 merge patch of a `Ticket`; only `username` is mutable, \
+            `version` must match the stored one",
+    ),
+    responses(
+        (status = 200, description = "Number of matched tickets", body = String, example = "1"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Ticket does not exist", body = ErrorBody),
+        (status = 409, description = "Ticket changed since it was read", body = ErrorBody),
+        (status = 422, description = "Patch names unknown or immutable fields", body = ErrorBody),
+    ),
+)]
 #[patch("/ticket/{id}")]
 pub async fn update_ticket(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
     id: web::Path<String>,
-    form: web::Json<Ticket>,
-) -> HttpResponse {
-    let mut data = form.into_inner();
-    data.id = ObjectId::parse_str(id.into_inner().to_string()).unwrap();
-    let result = db_interface.update_ticket(&client, &mut data).await;
-    match result {
-        Ok(result) => {
-            info!("{:?}", data);
-            HttpResponse::Ok().body(format!("{:?}", result.matched_count))
-        }
-        Err(err) => {
-            error!("{:?}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
+    if_match: Option<web::Header<IfMatch>>,
+    patch: web::Json<serde_json::Value>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::RaffleAdmin)?;
+    let ticket_id = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let ticket = db_interface
+        .get_ticket_by_id(ticket_id)
+        .await?
+        .pop()
+        .ok_or(ApiError::TicketNotFound)?;
+    let mut patch = patch.into_inner();
+    check_version(if_match, &mut patch, ticket.version)?;
+    let mut data = merge_patch::apply(&ticket, &patch, TICKET_FIELDS, TICKET_IMMUTABLE)?;
+    let result = db_interface.update_ticket(&data).await?;
+    ensure!(result > 0, VersionConflictSnafu);
+    data.version += 1;
+    audit::record(db_interface.as_ref(), &key, &req, "ticket", data.id, Some(&ticket), Some(&data)).await;
+    info!("{:?}", data);
+    Ok(HttpResponse::Ok()
+        .insert_header((ETAG, etag(data.version)))
+        .body(format!("{:?}", result)))
+}
+
+fn etag(version: i64) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+/// Sets the `ETag` of a single document fetched by id.
+fn with_etag<T>(
+    mut response: actix_web::HttpResponseBuilder,
+    result: &[T],
+    version: impl Fn(&T) -> i64,
+) -> actix_web::HttpResponseBuilder {
+    if let [document] = result {
+        response.insert_header((ETAG, etag(version(document))));
     }
+    response
+}
+
+/// Fails unless `If-Match` and the patch's `version` (both optional) name the `current` version.
+/// `version` is taken out of the patch, the storage layer bumps it.
+fn check_version(
+    if_match: Option<web::Header<IfMatch>>,
+    patch: &mut serde_json::Value,
+    current: i64,
+) -> Result<(), ApiError> {
+    // A missing header is parsed as an empty list
+    let header_matches = match if_match.map(web::Header::into_inner) {
+        None | Some(IfMatch::Any) => true,
+        Some(IfMatch::Items(tags)) => {
+            tags.is_empty() || tags.iter().any(|tag| tag.strong_eq(&etag(current)))
+        }
+    };
+    let body_matches = patch
+        .as_object_mut()
+        .and_then(|fields| fields.remove("version"))
+        .is_none_or(|version| version.as_i64() == Some(current));
+    ensure!(header_matches && body_matches, VersionConflictSnafu);
+    Ok(())
 }
+
+/// Raffle fields a merge patch may name; `version` is taken out by `check_version` before.
+const RAFFLE_FIELDS: &[&str] = &[
+    "_id",
+    "title",
+    "description",
+    "status",
+    "ticket_amount",
+    "ticket_price",
+    "ticket_token_name",
+    "rule",
+    "prizes",
+    "date_start",
+    "date_end",
+    "announcements",
+    "validation",
+    "date_created",
+    "date_updated",
+];
+
+/// Raffle fields a merge patch may not change.
+const RAFFLE_IMMUTABLE: &[&str] = &["_id", "date_created", "date_updated"];
+
+/// Ticket fields a merge patch may name.
+const TICKET_FIELDS: &[&str] = &[
+    "_id",
+    "raffle_id",
+    "username",
+    "spl_tx_signature",
+    "amount_send",
+    "amount",
+    "date_created",
+    "date_updated",
+];
+
+/// Ticket fields a merge patch may not change; the sold amount is fixed by the SPL transfer.
+const TICKET_IMMUTABLE: &[&str] = &[
+    "_id",
+    "raffle_id",
+    "spl_tx_signature",
+    "amount_send",
+    "amount",
+    "date_created",
+    "date_updated",
+];
 //endregion
 
 //region === DELETE ===
+#[utoipa::path(
+    tag = "raffle",
+    params(("id" = String, Path, description = "Raffle id")),
+    responses(
+        (status = 200, description = "Raffle removed", body = String, example = "ok"),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+    ),
+)]
 #[delete("/raffle/{id}")]
 pub async fn remove_raffle(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
     id: web::Path<String>,
-) -> HttpResponse {
-    let data = ObjectId::parse_str(id.into_inner()).unwrap();
-    let result = db_interface.remove_raffle(&client, data).await;
-    match result {
-        Ok(_) => {
-            info!("{:?}", data);
-            HttpResponse::Ok().body("ok")
-        }
-        Err(err) => {
-            error!("{:?}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
-        }
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::RaffleAdmin)?;
+    let data = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let before = db_interface.get_raffle_by_id(data).await?.pop();
+    let removed = db_interface.remove_raffle(data).await?;
+    if let Some(before) = before.filter(|_| removed > 0) {
+        audit::record(db_interface.as_ref(), &key, &req, "raffle", data, Some(&before), None).await;
     }
+    info!("{:?}", data);
+    Ok(HttpResponse::Ok().body("ok"))
 }
 
+#[utoipa::path(
+    tag = "ticket",
+    params(("id" = String, Path, description = "Ticket id")),
+    responses(
+        (status = 200, description = "Ticket removed", body = String, example = "ok"),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+    ),
+)]
 #[delete("/ticket/{id}")]
 pub async fn remove_ticket(
-    client: web::Data<Client>,
-    db_interface: web::Data<DatabaseRaffle>,
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
     id: web::Path<String>,
-) -> HttpResponse {
-    let data = ObjectId::parse_str(id.into_inner()).unwrap();
-    let result = db_interface.remove_ticket(&client, data).await;
-    match result {
-        Ok(_) => {
-            info!("{:?}", data);
-            HttpResponse::Ok().body("ok")
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::RaffleAdmin)?;
+    let data = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let before = db_interface.get_ticket_by_id(data).await?.pop();
+    let removed = db_interface.remove_ticket(data).await?;
+    if let Some(before) = before.filter(|_| removed > 0) {
+        audit::record(db_interface.as_ref(), &key, &req, "ticket", data, Some(&before), None).await;
+    }
+    info!("{:?}", data);
+    Ok(HttpResponse::Ok().body("ok"))
+}
+//endregion
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App, HttpMessage, HttpServer};
+
+    use super::*;
+    use crate::api_keys;
+    use crate::db_sql::DatabaseSql;
+    use crate::error;
+    use crate::repository::IdempotencyRecord;
+
+    async fn repository() -> Arc<dyn RaffleRepository> {
+        Arc::new(DatabaseSql::connect("sqlite::memory:").await.unwrap())
+    }
+
+    async fn call(
+        repository: &Arc<dyn RaffleRepository>,
+        req: test::TestRequest,
+    ) -> (StatusCode, ErrorBody) {
+        let (status, body) = send(repository, req).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn send(
+        repository: &Arc<dyn RaffleRepository>,
+        req: test::TestRequest,
+    ) -> (StatusCode, actix_web::web::Bytes) {
+        send_as(repository, req, &Scope::ALL).await
+    }
+
+    /// Sends `req` authenticated with a key holding `scopes`.
+    async fn send_as(
+        repository: &Arc<dyn RaffleRepository>,
+        req: test::TestRequest,
+        scopes: &[Scope],
+    ) -> (StatusCode, actix_web::web::Bytes) {
+        send_with(repository, req, scopes, Settings::default()).await
+    }
+
+    async fn send_with(
+        repository: &Arc<dyn RaffleRepository>,
+        req: test::TestRequest,
+        scopes: &[Scope],
+        settings: Settings,
+    ) -> (StatusCode, actix_web::web::Bytes) {
+        send_on(repository, req, scopes, settings, EventBus::default()).await
+    }
+
+    /// Sends `req` with the events published on `event_bus`.
+    async fn send_on(
+        repository: &Arc<dyn RaffleRepository>,
+        req: test::TestRequest,
+        scopes: &[Scope],
+        settings: Settings,
+        event_bus: EventBus,
+    ) -> (StatusCode, actix_web::web::Bytes) {
+        let key = ApiKey {
+            name: "test".to_string(),
+            scopes: scopes.to_vec(),
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(key.clone());
+                    srv.call(req)
+                })
+                .app_data(web::Data::from(repository.clone()))
+                .app_data(web::Data::new(event_bus))
+                .app_data(web::Data::new(settings))
+                .app_data(web::JsonConfig::default().error_handler(error::json_error_handler))
+                .service(
+                    web::scope("/api/v1")
+                        .service(add_raffle)
+                        .service(add_ticket)
+                        .service(get_raffle)
+                        .service(get_raffle_stats)
+                        .service(update_raffle)
+                        .service(remove_ticket)
+                        .service(audit::list_audit)
+                        .service(api_keys::create_key),
+                ),
+        )
+        .await;
+        let resp = test::call_service(&app, req.to_request()).await;
+        let status = resp.status();
+        (status, test::read_body(resp).await)
+    }
+
+    fn ticket_for(raffle_id: ObjectId, signature: &str) -> Ticket {
+        Ticket {
+            id: ObjectId::new(),
+            raffle_id,
+            username: "user".to_string(),
+            spl_tx_signature: signature.to_string(),
+            amount_send: 0.0,
+            amount: 0,
+            date_created: 0,
+            date_updated: 0,
+            version: 0,
+        }
+    }
+
+    #[actix_web::test]
+    async fn malformed_ids_are_bad_requests() {
+        let repository = repository().await;
+        for req in [
+            test::TestRequest::get().uri("/api/v1/raffle/not-an-id"),
+            test::TestRequest::get().uri("/api/v1/raffle/1234/stats"),
+            test::TestRequest::delete().uri("/api/v1/ticket/zzzzzzzzzzzzzzzzzzzzzzzz"),
+            test::TestRequest::patch()
+                .uri("/api/v1/raffle/xyz")
+                .set_json(serde_json::json!({
+                    "title": "t", "description": "d", "ticket_amount": 1,
+                    "ticket_price": 1.0, "ticket_token_name": "USDC"
+                })),
+        ] {
+            let (status, body) = call(&repository, req).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body.code, "invalid_id");
+        }
+    }
+
+    #[actix_web::test]
+    async fn invalid_raffle_lists_every_violation() {
+        let repository = repository().await;
+        let req = test::TestRequest::post()
+            .uri("/api/v1/raffle")
+            .set_json(serde_json::json!({
+                "title": " ", "description": "d", "ticket_amount": 0,
+                "ticket_price": 0.0, "ticket_token_name": "DOGE",
+                "date_start": 1700000000, "date_end": 1600000000
+            }));
+        let (status, body) = call(&repository, req).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.code, "invalid_raffle");
+        let fields: Vec<&str> = body.violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(fields, ["title", "ticket_price", "ticket_amount", "ticket_token_name", "date_end"]);
+        assert!(repository.get_all_raffles().await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn raffle_patch_keeps_omitted_fields() {
+        let repository = repository().await;
+        let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 5,
+            "ticket_price": 1.0, "ticket_token_name": "USDC", "rule": "r"
+        }))
+        .unwrap();
+        repository.insert_raffle(&mut raffle).await.unwrap();
+        let uri = format!("/api/v1/raffle/{}", raffle.id.to_hex());
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .insert_header(("Content-Type", "application/merge-patch+json"))
+            .set_payload(r#"{"title": "new", "date_end": 1900000000}"#);
+        let (status, _) = send(&repository, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let stored = repository.get_raffle_by_id(raffle.id).await.unwrap().pop().unwrap();
+        assert_eq!(stored.title, "new");
+        assert_eq!(stored.date_end, Some(1900000000));
+        assert_eq!((stored.status.as_str(), stored.rule.as_str()), ("created", "r"));
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .set_json(serde_json::json!({"title": "other", "date_created": 1}));
+        let (status, body) = call(&repository, req).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.code, "immutable_field");
+        assert_eq!(body.violations[0].field, "date_created");
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .set_json(serde_json::json!({"titel": "other", "tickets_sold": 0, "rule": "x"}));
+        let (status, body) = call(&repository, req).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.code, "unknown_field");
+        let fields: Vec<&str> = body.violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(fields, ["titel", "tickets_sold"]);
+        let stored = repository.get_raffle_by_id(raffle.id).await.unwrap().pop().unwrap();
+        assert_eq!(stored.rule, "r");
+    }
+
+    #[actix_web::test]
+    async fn patchable_fields_cover_the_models() {
+        let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 5,
+            "ticket_price": 1.0, "ticket_token_name": "USDC",
+            "announcements": {"raffle.opened": {"title": "t"}}, "validation": {"check_tx_status": true}
+        }))
+        .unwrap();
+        raffle.date_start = Some(1);
+        let raffle = serde_json::to_value(raffle).unwrap();
+        let ticket = serde_json::to_value(ticket_for(ObjectId::new(), "sig")).unwrap();
+        for (value, fields) in [(raffle, RAFFLE_FIELDS), (ticket, TICKET_FIELDS)] {
+            for field in value.as_object().unwrap().keys().filter(|field| *field != "version") {
+                assert!(fields.contains(&field.as_str()), "{} is missing", field);
+            }
         }
-        Err(err) => {
-            error!("{:?}", err);
-            HttpResponse::InternalServerError().body(err.to_string())
+    }
+
+    #[actix_web::test]
+    async fn mutations_are_audited() {
+        let repository = repository().await;
+        let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 5,
+            "ticket_price": 1.0, "ticket_token_name": "USDC"
+        }))
+        .unwrap();
+        repository.insert_raffle(&mut raffle).await.unwrap();
+        let req = test::TestRequest::patch()
+            .uri(&format!("/api/v1/raffle/{}", raffle.id.to_hex()))
+            .set_json(serde_json::json!({"title": "new"}));
+        assert_eq!(send(&repository, req).await.0, StatusCode::OK);
+        let mut ticket = ticket_for(raffle.id, "sig");
+        repository.insert_ticket(&mut ticket).await.unwrap();
+        let req = test::TestRequest::delete().uri(&format!("/api/v1/ticket/{}", ticket.id.to_hex()));
+        assert_eq!(send(&repository, req).await.0, StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/api/v1/audit?sort=desc");
+        let (status, body) = send(&repository, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let page: Page<AuditEntry> = serde_json::from_slice(&body).unwrap();
+        let [deleted, updated] = &page.items[..] else {
+            panic!("expected two entries, got {:?}", page.items);
+        };
+        assert_eq!(
+            (deleted.action.as_str(), deleted.route.as_str(), deleted.document_id),
+            ("delete", "DELETE /api/v1/ticket/{id}", ticket.id)
+        );
+        assert!(deleted.changes.iter().all(|change| change.after.is_null()));
+        assert_eq!((updated.actor.as_str(), updated.action.as_str()), ("test", "update"));
+        let fields: Vec<_> = updated.changes.iter().map(|change| change.field.as_str()).collect();
+        // `date_updated` changes too when the second ticked over since the insert
+        assert!(fields.contains(&"title") && fields.contains(&"version"), "{:?}", fields);
+        assert!(fields.iter().all(|field| ["title", "version", "date_updated"].contains(field)), "{:?}", fields);
+
+        let req = test::TestRequest::get().uri("/api/v1/audit");
+        assert_eq!(send_as(&repository, req, &[Scope::Read]).await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn stale_versions_conflict() {
+        let repository = repository().await;
+        // A client's version is ignored, every raffle starts at 0
+        let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 5,
+            "ticket_price": 1.0, "ticket_token_name": "USDC", "version": 7
+        }))
+        .unwrap();
+        repository.insert_raffle(&mut raffle).await.unwrap();
+        let uri = format!("/api/v1/raffle/{}", raffle.id.to_hex());
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .insert_header(("If-Match", "\"0\""))
+            .set_json(serde_json::json!({"title": "first"}));
+        let (status, _) = send(&repository, req).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Both the header and the body version of a second editor are outdated now
+        for req in [
+            test::TestRequest::patch()
+                .uri(&uri)
+                .insert_header(("If-Match", "\"0\""))
+                .set_json(serde_json::json!({"title": "second"})),
+            test::TestRequest::patch()
+                .uri(&uri)
+                .set_json(serde_json::json!({"title": "second", "version": 0})),
+        ] {
+            let (status, body) = call(&repository, req).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            assert_eq!(body.code, "version_conflict");
         }
+        let stored = repository.get_raffle_by_id(raffle.id).await.unwrap().pop().unwrap();
+        assert_eq!((stored.title.as_str(), stored.version), ("first", 1));
+    }
+
+    #[actix_web::test]
+    async fn idempotent_retries_replay_the_first_response() {
+        let repository = repository().await;
+        let raffle = serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 5,
+            "ticket_price": 1.0, "ticket_token_name": "USDC"
+        });
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/raffle")
+                .insert_header(("Idempotency-Key", "retry-1"))
+                .set_json(&raffle);
+            let (status, body) = send(&repository, req).await;
+            assert_eq!((status, &body[..]), (StatusCode::OK, &b"ok"[..]));
+        }
+        assert_eq!(repository.get_all_raffles().await.unwrap().len(), 1);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/raffle")
+            .insert_header(("Idempotency-Key", "retry-1"))
+            .set_json(serde_json::json!({
+                "title": "other", "description": "d", "ticket_amount": 5,
+                "ticket_price": 1.0, "ticket_token_name": "USDC"
+            }));
+        let (status, body) = call(&repository, req).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.code, "idempotency_key_reused");
+    }
+
+    #[actix_web::test]
+    async fn idempotency_keys_are_scoped_and_leased() {
+        let repository = repository().await;
+        let now = chrono::Utc::now().timestamp();
+        for (key, date_created) in [("other:shared", now), ("test:crashed", now - 3600)] {
+            let record = IdempotencyRecord {
+                key: key.to_string(),
+                request_hash: "hash".to_string(),
+                response: None,
+                date_created,
+            };
+            assert!(repository.claim_idempotency_key(&record).await.unwrap().is_none());
+        }
+        for key in ["shared", "crashed"] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/raffle")
+                .insert_header(("Idempotency-Key", key))
+                .set_json(serde_json::json!({
+                    "title": key, "description": "d", "ticket_amount": 5,
+                    "ticket_price": 1.0, "ticket_token_name": "USDC"
+                }));
+            let (status, body) = send(&repository, req).await;
+            assert_eq!((status, &body[..]), (StatusCode::OK, &b"ok"[..]), "{}", key);
+        }
+        let record = IdempotencyRecord {
+            key: "test:shared".to_string(),
+            request_hash: "hash".to_string(),
+            response: None,
+            date_created: now,
+        };
+        let stored = repository.claim_idempotency_key(&record).await.unwrap().unwrap();
+        assert_eq!(stored.response.unwrap().status, 200);
+    }
+
+    #[actix_web::test]
+    async fn only_the_last_ticket_closes_the_raffle() {
+        let repository = repository().await;
+        let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 3,
+            "ticket_price": 1.0, "ticket_token_name": "USDC"
+        }))
+        .unwrap();
+        repository.insert_raffle(&mut raffle).await.unwrap();
+        let mut results = Vec::new();
+        for requested in [2, 2, 1] {
+            let mut ticket: Ticket = serde_json::from_value(serde_json::json!({
+                "raffle_id": raffle.id, "username": "u", "spl_tx_signature": "s", "amount": requested
+            }))
+            .unwrap();
+            let closed = repository.allocate_ticket(&mut ticket).await.unwrap();
+            results.push((ticket.amount, closed));
+        }
+        assert_eq!(results, [(2, false), (1, true), (0, false)]);
+        let stored = repository.get_raffle_by_id(raffle.id).await.unwrap().pop().unwrap();
+        assert_eq!(stored.status, "closed");
+        assert_eq!(stored.date_updated, raffle.date_updated, "sales don't touch the raffle's date");
+    }
+
+    #[actix_web::test]
+    async fn raffles_created_running_are_opened() {
+        let repository = repository().await;
+        let event_bus = EventBus::default();
+        let mut events = event_bus.subscribe();
+        for status in ["drawn", "running"] {
+            let req = test::TestRequest::post().uri("/api/v1/raffle").set_json(serde_json::json!({
+                "title": status, "description": "d", "ticket_amount": 5,
+                "ticket_price": 1.0, "ticket_token_name": "USDC", "status": status
+            }));
+            let (status, _) = send_on(&repository, req, &Scope::ALL, Settings::default(), event_bus.clone()).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::RaffleOpened);
+        assert_eq!(event.data["title"], "running");
+        assert!(events.try_recv().is_err(), "a raffle created as drawn is not opened");
+        let mut statuses: Vec<String> =
+            repository.get_all_raffles().await.unwrap().into_iter().map(|raffle| raffle.status).collect();
+        statuses.sort();
+        assert_eq!(statuses, ["created", "running"]);
+    }
+
+    #[actix_web::test]
+    async fn scopes_are_enforced_per_route() {
+        let repository = repository().await;
+        let bot = [Scope::Read, Scope::TicketSubmit];
+        let req = test::TestRequest::get().uri("/api/v1/raffle/0");
+        let (status, _) = send_as(&repository, req, &bot).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/v1/ticket/{}", ObjectId::new().to_hex()));
+        let (status, body) = send_as(&repository, req, &bot).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "forbidden");
+    }
+
+    #[actix_web::test]
+    async fn key_names_are_unique_and_not_reserved() {
+        let repository = repository().await;
+        let mut settings = Settings::default();
+        settings.auth.keys.insert("bot".to_string(), Default::default());
+        let admin = [Scope::KeysAdmin];
+        let create = |name: &str| {
+            test::TestRequest::post()
+                .uri("/api/v1/keys")
+                .set_json(serde_json::json!({"name": name, "scopes": ["read"]}))
+        };
+        let (status, _) = send_with(&repository, create("ops"), &admin, settings.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        for name in ["ops", "API_BEARER_TOKEN", "bot", "jwt:alice", "discord:bob"] {
+            let (status, body) = send_with(&repository, create(name), &admin, settings.clone()).await;
+            assert_eq!(status, StatusCode::CONFLICT, "{}", name);
+            let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body.code, "api_key_name_taken");
+        }
+        assert_eq!(repository.get_api_keys().await.unwrap().len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn missing_raffle_is_not_found() {
+        let repository = repository().await;
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/v1/raffle/{}/stats", ObjectId::new().to_hex()));
+        let (status, body) = call(&repository, req).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body.code, "raffle_not_found");
+    }
+
+    #[actix_web::test]
+    async fn upstream_failures_are_reported() {
+        let solscan = HttpServer::new(|| {
+            App::new().route(
+                "/transaction/{signature}",
+                web::get().to(|signature: web::Path<String>| async move {
+                    match signature.as_str() {
+                        "broken" => HttpResponse::Ok().body("{\"txHash\": [1, 2"),
+                        "missing" => HttpResponse::NotFound().finish(),
+                        _ => HttpResponse::Ok().body(
+                            r#"{"txHash":"valid","blockTime":1650000000,"status":"Success",
+                                "tokenTransfers":[{"amount":"1000000",
+                                "token":{"symbol":"USDC","decimals":6}}]}"#,
+                        ),
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = solscan.addrs()[0];
+        actix_web::rt::spawn(solscan.run());
+        let mut settings = Settings::default();
+        settings.solscan.api_url = format!("http://{}", address);
+
+        let repository = repository().await;
+        let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+            "title": "t", "description": "d", "ticket_amount": 5,
+            "ticket_price": 1.0, "ticket_token_name": "USDC"
+        }))
+        .unwrap();
+        repository.insert_raffle(&mut raffle).await.unwrap();
+        // Unknown raffles are rejected before Solscan is asked
+        let unknown = ObjectId::new();
+        for (raffle_id, signature, expected_status, expected_code) in [
+            (raffle.id, "broken", StatusCode::BAD_GATEWAY, "upstream_error"),
+            (raffle.id, "missing", StatusCode::BAD_GATEWAY, "upstream_error"),
+            (unknown, "broken", StatusCode::NOT_FOUND, "raffle_not_found"),
+            (unknown, "valid", StatusCode::NOT_FOUND, "raffle_not_found"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/ticket")
+                .set_json(ticket_for(raffle_id, signature));
+            let (status, body) = send_with(&repository, req, &Scope::ALL, settings.clone()).await;
+            let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+            assert_eq!(status, expected_status, "{}", signature);
+            assert_eq!(body.code, expected_code, "{}", signature);
+        }
+    }
+
+    #[actix_web::test]
+    async fn raffles_override_the_instance_checks() {
+        let solscan = HttpServer::new(|| {
+            App::new().route(
+                "/transaction/{signature}",
+                web::get().to(|| async {
+                    HttpResponse::Ok().body(
+                        r#"{"txHash":"failed","blockTime":1650000000,"status":"Fail",
+                            "tokenTransfers":[{"amount":"1000000","destination_owner":"elsewhere",
+                            "token":{"symbol":"USDC","decimals":6}}]}"#,
+                    )
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = solscan.addrs()[0];
+        actix_web::rt::spawn(solscan.run());
+        let mut settings = Settings::default();
+        settings.solscan.api_url = format!("http://{}", address);
+        settings.validation.check_tx_status = true;
+        settings.validation.check_raffle_destination = true;
+        settings.validation.sol_wallet = Some("wallet".to_string());
+
+        let repository = repository().await;
+        let mut raffles = Vec::new();
+        for validation in [
+            serde_json::json!({}),
+            serde_json::json!({"check_tx_status": false, "check_raffle_destination": false}),
+        ] {
+            let mut raffle: Raffle = serde_json::from_value(serde_json::json!({
+                "title": "t", "description": "d", "ticket_amount": 5,
+                "ticket_price": 1.0, "ticket_token_name": "USDC", "validation": validation
+            }))
+            .unwrap();
+            repository.insert_raffle(&mut raffle).await.unwrap();
+            raffles.push(raffle.id);
+        }
+        let stored = repository.get_raffle_by_id(raffles[1]).await.unwrap().pop().unwrap();
+        assert_eq!(stored.validation.check_tx_status, Some(false));
+        assert_eq!(stored.validation.check_token_symbol, None);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/ticket")
+            .set_json(ticket_for(raffles[0], "paid"));
+        let (status, body) = send_with(&repository, req, &Scope::ALL, settings.clone()).await;
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.code, "tx_status_invalid");
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/ticket")
+            .set_json(ticket_for(raffles[1], "free"));
+        let (status, body) = send_with(&repository, req, &Scope::ALL, settings.clone()).await;
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"You got 1 Tickets"[..]));
+
+        let req = test::TestRequest::patch()
+            .uri(&format!("/api/v1/raffle/{}", raffles[1].to_hex()))
+            .set_json(serde_json::json!({
+                "validation": {"check_raffle_destination": null, "sol_wallet": ""}
+            }));
+        let (status, body) = send_with(&repository, req, &Scope::ALL, settings).await;
+        let body: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body.violations[0].field, "validation.sol_wallet");
     }
 }
-//endregion
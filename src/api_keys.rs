@@ -0,0 +1,168 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use log::info;
+use snafu::prelude::*;
+
+use crate::audit;
+use crate::auth::{self, generate_token, hash_token, ApiKey, Scope};
+use crate::error::{ApiError, ApiKeyNameTakenSnafu, InvalidIdSnafu, InvalidRequestSnafu};
+use crate::model::*;
+use crate::repository::{RaffleRepository, StoredApiKey};
+use crate::settings::Settings;
+
+#[utoipa::path(
+    tag = "keys",
+    request_body = NewApiKey,
+    responses(
+        (status = 201, description = "Key created, the token is only returned now", body = CreatedApiKey),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Missing `keys:admin` scope", body = ErrorBody),
+        (status = 409, description = "Name is used by another key or reserved", body = ErrorBody),
+    ),
+)]
+#[post("/keys")]
+pub async fn create_key(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    form: web::Json<NewApiKey>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::KeysAdmin)?;
+    let form = form.into_inner();
+    ensure!(
+        !form.name.trim().is_empty(),
+        InvalidRequestSnafu {
+            message: "name must not be empty"
+        }
+    );
+    let scopes = parse_scopes(&form.scopes)?;
+    // The name identifies the key in the audit log, rate limits and idempotency keys.
+    let taken = auth::is_reserved_name(&settings, &form.name)
+        || db_interface.get_api_keys().await?.iter().any(|stored| stored.name == form.name);
+    ensure!(!taken, ApiKeyNameTakenSnafu { name: form.name });
+
+    let (token, prefix) = generate_token();
+    let stored = StoredApiKey {
+        info: ApiKeyInfo {
+            id: ObjectId::new(),
+            name: form.name,
+            scopes,
+            prefix,
+            date_created: chrono::Utc::now().timestamp(),
+            date_expires: form.date_expires,
+            date_last_used: None,
+            date_revoked: None,
+        },
+        key_hash: hash_token(&token),
+    };
+    db_interface.insert_api_key(&stored).await?;
+    let info = Some(&stored.info);
+    audit::record(db_interface.as_ref(), &key, &req, "api_key", stored.info.id, None, info).await;
+    info!("API key '{}' created by '{}'", stored.info.name, key.name);
+    Ok(HttpResponse::Created().json(CreatedApiKey {
+        key: stored.info,
+        token,
+    }))
+}
+
+#[utoipa::path(
+    tag = "keys",
+    responses(
+        (status = 200, description = "All stored keys, without tokens", body = Vec<ApiKeyInfo>),
+        (status = 403, description = "Missing `keys:admin` scope", body = ErrorBody),
+    ),
+)]
+#[get("/keys")]
+pub async fn list_keys(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::KeysAdmin)?;
+    Ok(HttpResponse::Ok().json(db_interface.get_api_keys().await?))
+}
+
+#[utoipa::path(
+    tag = "keys",
+    params(("id" = String, Path, description = "Key id")),
+    responses(
+        (status = 200, description = "New token, the old one stops working", body = CreatedApiKey),
+        (status = 403, description = "Missing `keys:admin` scope", body = ErrorBody),
+        (status = 404, description = "No active key with this id", body = ErrorBody),
+    ),
+)]
+#[post("/keys/{id}/rotate")]
+pub async fn rotate_key(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::KeysAdmin)?;
+    let id = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let before = db_interface.get_api_key_by_id(id).await?;
+    let (token, prefix) = generate_token();
+    if db_interface.rotate_api_key(id, &hash_token(&token), &prefix).await? == 0 {
+        return Err(ApiError::ApiKeyNotFound);
+    }
+    let rotated = db_interface
+        .get_api_key_by_id(id)
+        .await?
+        .ok_or(ApiError::ApiKeyNotFound)?;
+    audit::record(db_interface.as_ref(), &key, &req, "api_key", id, before.as_ref(), Some(&rotated)).await;
+    info!("API key '{}' rotated by '{}'", rotated.name, key.name);
+    Ok(HttpResponse::Ok().json(CreatedApiKey {
+        key: rotated,
+        token,
+    }))
+}
+
+#[utoipa::path(
+    tag = "keys",
+    params(("id" = String, Path, description = "Key id")),
+    responses(
+        (status = 200, description = "Key revoked", body = String, example = "ok"),
+        (status = 403, description = "Missing `keys:admin` scope", body = ErrorBody),
+        (status = 404, description = "No active key with this id", body = ErrorBody),
+    ),
+)]
+#[delete("/keys/{id}")]
+pub async fn revoke_key(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::KeysAdmin)?;
+    let id = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let now = chrono::Utc::now().timestamp();
+    if db_interface.revoke_api_key(id, now).await? == 0 {
+        return Err(ApiError::ApiKeyNotFound);
+    }
+    if let Some(revoked) = db_interface.get_api_key_by_id(id).await? {
+        let before = ApiKeyInfo {
+            date_revoked: None,
+            ..revoked.clone()
+        };
+        audit::record(db_interface.as_ref(), &key, &req, "api_key", id, Some(&before), Some(&revoked)).await;
+    }
+    info!("API key {} revoked by '{}'", id, key.name);
+    Ok(HttpResponse::Ok().body("ok"))
+}
+
+fn parse_scopes(scopes: &[String]) -> Result<Vec<String>, ApiError> {
+    ensure!(
+        !scopes.is_empty(),
+        InvalidRequestSnafu {
+            message: "scopes must not be empty"
+        }
+    );
+    scopes
+        .iter()
+        .map(|scope| {
+            scope
+                .parse::<Scope>()
+                .map(|scope| scope.to_string())
+                .map_err(|message| ApiError::InvalidRequest { message })
+        })
+        .collect()
+}
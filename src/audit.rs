@@ -0,0 +1,138 @@
+use std::collections::BTreeSet;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use log::error;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::auth::{ApiKey, Scope};
+use crate::error::{ApiError, InvalidIdSnafu};
+use crate::model::*;
+use crate::repository::{AuditFilter, PageRequest, RaffleRepository};
+use snafu::prelude::*;
+
+/// Appends an entry for a change to `document_id` made by `key` through `req`.
+///
+/// `before` is `None` for a create, `after` for a delete. A failed write is logged but does
+/// not fail the request, the change itself is already stored.
+pub async fn record<T: Serialize>(
+    db_interface: &dyn RaffleRepository,
+    key: &ApiKey,
+    req: &HttpRequest,
+    collection: &str,
+    document_id: ObjectId,
+    before: Option<&T>,
+    after: Option<&T>,
+) {
+    let action = match (before, after) {
+        (None, _) => "create",
+        (_, None) => "delete",
+        _ => "update",
+    };
+    let entry = AuditEntry {
+        id: ObjectId::new(),
+        actor: key.name.clone(),
+        action: action.to_string(),
+        route: format!(
+            "{} {}",
+            req.method(),
+            req.match_pattern().unwrap_or_else(|| req.path().to_string())
+        ),
+        collection: collection.to_string(),
+        document_id,
+        changes: changes(to_value(before), to_value(after)),
+        date_created: chrono::Utc::now().timestamp(),
+    };
+    if let Err(err) = db_interface.insert_audit_entry(&entry).await {
+        error!("Could not write audit entry {:?}: {}", entry, err);
+    }
+}
+
+fn to_value<T: Serialize>(document: Option<&T>) -> Value {
+    document
+        .and_then(|document| serde_json::to_value(document).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// Top-level fields that differ between `before` and `after`, sorted by name.
+fn changes(before: Value, after: Value) -> Vec<AuditChange> {
+    let field = |document: &Value, name: &str| document.get(name).cloned().unwrap_or(Value::Null);
+    let fields: BTreeSet<&String> = before
+        .as_object()
+        .into_iter()
+        .chain(after.as_object())
+        .flat_map(|object| object.keys())
+        .collect();
+    fields
+        .into_iter()
+        .filter_map(|name| {
+            let (before, after) = (field(&before, name), field(&after, name));
+            (before != after).then(|| AuditChange {
+                field: name.clone(),
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+#[utoipa::path(
+    tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "One page of audit entries", body = Page<AuditEntry>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Missing `audit:read` scope", body = ErrorBody),
+    ),
+)]
+#[get("/audit")]
+pub async fn list_audit(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::AuditRead)?;
+    let query = query.into_inner();
+    let parse = |value: Option<String>| {
+        value
+            .as_deref()
+            .map(ObjectId::parse_str)
+            .transpose()
+            .context(InvalidIdSnafu)
+    };
+    let filter = AuditFilter {
+        actor: query.actor,
+        action: query.action,
+        collection: query.collection,
+        document_id: parse(query.document_id)?,
+        date_from: query.date_from,
+        date_to: query.date_to,
+    };
+    let page = PageRequest::new(query.limit, parse(query.cursor)?, query.sort);
+    Ok(HttpResponse::Ok().json(db_interface.find_audit_entries(&filter, page).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn changes_list_differing_fields() {
+        let before = json!({"title": "a", "status": "running", "version": 1});
+        let after = json!({"title": "b", "status": "running", "version": 2, "rule": "x"});
+        let fields: Vec<_> = changes(before.clone(), after)
+            .into_iter()
+            .map(|change| (change.field, change.before, change.after))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("rule".to_string(), Value::Null, json!("x")),
+                ("title".to_string(), json!("a"), json!("b")),
+                ("version".to_string(), json!(1), json!(2)),
+            ]
+        );
+        assert_eq!(changes(before, Value::Null).len(), 3);
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+use std::future::{ready, Ready};
+use std::str::FromStr;
+
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use log::warn;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::error::ApiError;
+use crate::jwt;
+use crate::repository::{self, RaffleRepository};
+use crate::settings::Settings;
+
+/// `last_used` is written at most this often per key.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+/// Name of the `API_BEARER_TOKEN` key.
+const BEARER_KEY_NAME: &str = "API_BEARER_TOKEN";
+
+/// Permission granted to an API key.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Scope {
+    /// Every `GET` route.
+    Read,
+    /// `POST /ticket`.
+    TicketSubmit,
+    /// Creating, updating and removing raffles and tickets.
+    RaffleAdmin,
+    /// Managing API keys.
+    KeysAdmin,
+    /// `GET /audit`.
+    AuditRead,
+    /// Managing webhooks.
+    WebhooksAdmin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [
+        Scope::Read,
+        Scope::TicketSubmit,
+        Scope::RaffleAdmin,
+        Scope::KeysAdmin,
+        Scope::AuditRead,
+        Scope::WebhooksAdmin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::TicketSubmit => "ticket:submit",
+            Scope::RaffleAdmin => "raffle:admin",
+            Scope::KeysAdmin => "keys:admin",
+            Scope::AuditRead => "audit:read",
+            Scope::WebhooksAdmin => "webhooks:admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == value)
+            .ok_or_else(|| format!("unknown scope '{}'", value))
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// The API key a request was authenticated with, set by the bearer middleware.
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl ApiKey {
+    pub fn require(&self, scope: Scope) -> Result<(), ApiError> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden { scope })
+        }
+    }
+}
+
+impl FromRequest for ApiKey {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<ApiKey>().cloned().ok_or(ApiError::Unauthorized))
+    }
+}
+
+/// Returns the key matching `token`, from the `auth` settings, a JWT verified as configured in
+/// `jwt` or the database. Revoked and expired database keys are rejected; hashes are compared in
+/// constant time.
+pub async fn authenticate(
+    db_interface: &dyn RaffleRepository,
+    settings: &Settings,
+    token: &str,
+) -> Result<Option<ApiKey>, repository::Error> {
+    let token_hash = hash_token(token);
+    let mut configured_key = None;
+    for (configured_token, key) in configured_keys(settings) {
+        if bool::from(hash_token(configured_token).as_bytes().ct_eq(token_hash.as_bytes())) {
+            configured_key = Some(key);
+        }
+    }
+    if configured_key.is_some() {
+        return Ok(configured_key);
+    }
+    if jwt::looks_like_jwt(token) {
+        match jwt::JwtConfig::from_settings(&settings.jwt) {
+            Ok(Some(jwt)) => return Ok(jwt.verify(token)),
+            Ok(None) => {}
+            // Checked at startup, see `Settings::load`
+            Err(err) => warn!("Not verifying JWT: {}", err),
+        }
+    }
+
+    let Some(stored) = db_interface.get_api_key_by_hash(&token_hash).await? else {
+        return Ok(None);
+    };
+    let info = stored.info;
+    let now = chrono::Utc::now().timestamp();
+    if !bool::from(stored.key_hash.as_bytes().ct_eq(token_hash.as_bytes()))
+        || info.date_revoked.is_some()
+        || info.date_expires.is_some_and(|expires| expires <= now)
+    {
+        return Ok(None);
+    }
+    if info
+        .date_last_used
+        .is_none_or(|used| used + TOUCH_INTERVAL_SECS <= now)
+    {
+        db_interface.touch_api_key(info.id, now).await?;
+    }
+    Ok(Some(ApiKey {
+        name: info.name,
+        scopes: info.scopes.iter().filter_map(|scope| scope.parse().ok()).collect(),
+    }))
+}
+
+/// Hex SHA-256 of a token. Tokens are random, so a fast hash is enough.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Returns a new random token and its displayed prefix.
+pub fn generate_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("rk_{}", hex::encode(bytes));
+    let prefix = token[..10].to_string();
+    (token, prefix)
+}
+
+/// Tokens and keys of `auth.keys`, and `auth.bearer_token` as a key with every scope.
+/// Whether `name` is used by a configured key or a JWT or Discord identity.
+pub fn is_reserved_name(settings: &Settings, name: &str) -> bool {
+    name == BEARER_KEY_NAME
+        || settings.auth.keys.contains_key(name)
+        || name.starts_with("jwt:")
+        || name.starts_with("discord:")
+}
+
+fn configured_keys(settings: &Settings) -> impl Iterator<Item = (&str, ApiKey)> {
+    let bearer = settings.auth.bearer_token.as_deref().map(|token| {
+        let key = ApiKey {
+            name: BEARER_KEY_NAME.to_string(),
+            scopes: Scope::ALL.to_vec(),
+        };
+        (token, key)
+    });
+    let keys = settings.auth.keys.iter().map(|(name, key)| {
+        let api_key = ApiKey {
+            name: name.clone(),
+            scopes: key.scopes.clone(),
+        };
+        (key.token.as_str(), api_key)
+    });
+    bearer.into_iter().chain(keys).filter(|(token, _)| !token.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sql::DatabaseSql;
+    use crate::model::{ApiKeyInfo, ObjectId};
+    use crate::repository::StoredApiKey;
+    use crate::settings::KeySettings;
+
+    fn stored_key(token: &str, date_expires: Option<i64>) -> StoredApiKey {
+        StoredApiKey {
+            info: ApiKeyInfo {
+                id: ObjectId::new(),
+                name: "bot".to_string(),
+                scopes: vec!["read".to_string(), "ticket:submit".to_string()],
+                prefix: token[..10].to_string(),
+                date_created: 0,
+                date_expires,
+                date_last_used: None,
+                date_revoked: None,
+            },
+            key_hash: hash_token(token),
+        }
+    }
+
+    #[actix_web::test]
+    async fn stored_keys_authenticate_until_revoked_or_expired() {
+        let db = DatabaseSql::connect("sqlite::memory:").await.unwrap();
+        let mut settings = Settings::default();
+        let (token, _) = generate_token();
+        let key = stored_key(&token, None);
+        db.insert_api_key(&key).await.unwrap();
+
+        let authenticated = authenticate(&db, &settings, &token).await.unwrap().unwrap();
+        assert_eq!(authenticated.scopes, [Scope::Read, Scope::TicketSubmit]);
+        let info = db.get_api_key_by_id(key.info.id).await.unwrap().unwrap();
+        assert!(info.date_last_used.is_some());
+        assert!(authenticate(&db, &settings, "rk_unknown").await.unwrap().is_none());
+
+        let (rotated, prefix) = generate_token();
+        assert_eq!(db.rotate_api_key(key.info.id, &hash_token(&rotated), &prefix).await.unwrap(), 1);
+        assert!(authenticate(&db, &settings, &token).await.unwrap().is_none());
+        assert!(authenticate(&db, &settings, &rotated).await.unwrap().is_some());
+
+        assert_eq!(db.revoke_api_key(key.info.id, 1).await.unwrap(), 1);
+        assert!(authenticate(&db, &settings, &rotated).await.unwrap().is_none());
+
+        let (expired, _) = generate_token();
+        db.insert_api_key(&stored_key(&expired, Some(1))).await.unwrap();
+        assert!(authenticate(&db, &settings, &expired).await.unwrap().is_none());
+
+        settings.auth.keys.insert(
+            "ci".to_string(),
+            KeySettings {
+                token: "ci-token".to_string(),
+                scopes: vec![Scope::Read],
+            },
+        );
+        let configured = authenticate(&db, &settings, "ci-token").await.unwrap().unwrap();
+        assert_eq!((configured.name.as_str(), &configured.scopes[..]), ("ci", &[Scope::Read][..]));
+    }
+}
@@ -1,206 +1,717 @@
+use crate::model::{ApiKeyInfo, AuditEntry, Page, SortOrder, WebhookDelivery};
+use crate::repository::{
+    tickets_left, AuditFilter, BsonSnafu, Error, IdempotencyRecord, StoredApiKey, MongoSnafu, PageRequest, RaffleFilter,
+    RaffleNotFoundSnafu, RaffleRepository, StoredResponse, StoredWebhook, TicketFilter,
+};
+use crate::settings::DatabaseSettings;
 use crate::{ObjectId, Raffle, Ticket};
-use futures::stream::{ TryStreamExt};
-use lazy_static::lazy_static;
-use mongodb::bson::{doc};
-use mongodb::error::Error;
-use mongodb::results::{DeleteResult, InsertOneResult, UpdateResult};
-use mongodb::{Client};
-use std::{env};
-
-lazy_static! {
-    static ref DB_NAME: String = env::var("DB_NAME").unwrap_or_else(|_| "DB_Raffle".to_string());
-    static ref COLL_RAFFLE: String = env::var("COLL_RAFFLE").unwrap_or_else(|_| "Raffle".to_string());
-    static ref COLL_TICKET: String = env::var("COLL_TICKET").unwrap_or_else(|_| "Ticket".to_string());
-}
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::{ErrorKind, WriteError, WriteFailure};
+use mongodb::options::FindOptions;
+use mongodb::{Client, Collection, Database};
+use snafu::prelude::*;
 
+/// MongoDB backend.
 #[derive(Clone)]
 pub struct DatabaseRaffle {
-
+    client: Client,
+    settings: DatabaseSettings,
 }
 
 impl DatabaseRaffle {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(client: Client, settings: DatabaseSettings) -> Self {
+        Self { client, settings }
+    }
+
+    fn database(&self) -> Database {
+        self.client.database(&self.settings.name)
+    }
+
+    fn raffles(&self) -> Collection<Raffle> {
+        self.database()
+            .collection::<Raffle>(&self.settings.collections.raffle)
+    }
+
+    /// The raffles as plain documents, to reach `tickets_sold` which `Raffle` does not map.
+    fn raffle_documents(&self) -> Collection<Document> {
+        self.database()
+            .collection::<Document>(&self.settings.collections.raffle)
+    }
+
+    fn tickets(&self) -> Collection<Ticket> {
+        self.database()
+            .collection::<Ticket>(&self.settings.collections.ticket)
+    }
+
+    fn api_keys(&self) -> Collection<StoredApiKey> {
+        self.database()
+            .collection::<StoredApiKey>(&self.settings.collections.api_key)
+    }
+
+    fn audit(&self) -> Collection<AuditEntry> {
+        self.database()
+            .collection::<AuditEntry>(&self.settings.collections.audit)
+    }
+
+    fn webhooks(&self) -> Collection<StoredWebhook> {
+        self.database()
+            .collection::<StoredWebhook>(&self.settings.collections.webhook)
+    }
+
+    fn webhook_deliveries(&self) -> Collection<WebhookDelivery> {
+        self.database()
+            .collection::<WebhookDelivery>(&self.settings.collections.webhook_delivery)
+    }
+
+    /// Keyed by `_id`, so the key is unique without an extra index.
+    fn idempotency(&self) -> Collection<Document> {
+        self.database()
+            .collection::<Document>(&self.settings.collections.idempotency)
+    }
 
+    /// Tickets sold in the raffle, counted from its tickets once and then kept in the raffle's
+    /// `tickets_sold`.
+    async fn tickets_sold(&self, raffle_id: ObjectId) -> Result<u16, Error> {
+        let raffle = self
+            .raffle_documents()
+            .find_one(doc! {"_id": raffle_id}, None)
+            .await
+            .context(MongoSnafu)?
+            .context(RaffleNotFoundSnafu { id: raffle_id })?;
+        if let Ok(sold) = raffle.get_i32("tickets_sold") {
+            return Ok(u16::try_from(sold).unwrap_or_default());
         }
+        let sold = self
+            .get_tickets_by_id_raffle(raffle_id)
+            .await?
+            .iter()
+            .fold(0u16, |sold, t| sold.saturating_add(t.amount));
+        // Loses against a concurrent first count, whose compare-and-swap then fails ours
+        self.raffle_documents()
+            .update_one(
+                doc! {"_id": raffle_id, "tickets_sold": {"$exists": false}},
+                doc! {"$set": {"tickets_sold": sold as i32}},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(sold)
+    }
+
+    /// Adjusts `tickets_sold` of a raffle that counts it already.
+    async fn add_tickets_sold(&self, raffle_id: ObjectId, amount: i32) -> Result<(), Error> {
+        self.raffle_documents()
+            .update_one(
+                doc! {"_id": raffle_id, "tickets_sold": {"$exists": true}},
+                doc! {"$inc": {"tickets_sold": amount}},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(WriteError { code: 11000, .. }))
+    )
+}
+
+/// Matches the idempotency record of `claim` while nobody claimed its key anew.
+fn claim_filter(claim: &IdempotencyRecord) -> Document {
+    doc! {"_id": &claim.key, "request_hash": &claim.request_hash, "date_created": claim.date_created}
+}
+
+/// Matches `version`; documents written before versioning have no field and count as 0.
+fn version_filter(version: i64) -> Bson {
+    if version == 0 {
+        Bson::Document(doc! {"$in": [0i64, Bson::Null]})
+    } else {
+        Bson::Int64(version)
+    }
+}
+
+/// Adds the date range and keyset cursor conditions to `filter` and returns the matching
+/// sort/limit options.
+fn page_query(
+    filter: &mut Document,
+    date_from: Option<i64>,
+    date_to: Option<i64>,
+    page: &PageRequest,
+) -> FindOptions {
+    let mut date_created = Document::new();
+    if let Some(from) = date_from {
+        date_created.insert("$gte", from);
+    }
+    if let Some(to) = date_to {
+        date_created.insert("$lte", to);
+    }
+    if !date_created.is_empty() {
+        filter.insert("date_created", date_created);
+    }
+
+    let (cursor_op, direction) = match page.sort {
+        SortOrder::Asc => ("$gt", 1),
+        SortOrder::Desc => ("$lt", -1),
+    };
+    if let Some(cursor) = page.cursor {
+        filter.insert("_id", doc! { cursor_op: cursor });
     }
+    FindOptions::builder()
+        .sort(doc! {"_id": direction})
+        .limit(page.fetch_limit())
+        .build()
+}
 
+#[async_trait]
+impl RaffleRepository for DatabaseRaffle {
     //region === INSERT ===
-    pub async fn insert_raffle(
-        &self,
-        client: &Client,
-        raffle: &mut Raffle,
-    ) -> Result<InsertOneResult, Error> {
+    async fn insert_raffle(&self, raffle: &mut Raffle) -> Result<(), Error> {
         raffle.date_created = chrono::Utc::now().timestamp();
         raffle.date_updated = chrono::Utc::now().timestamp();
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Raffle>(COLL_RAFFLE.as_ref());
         raffle.id = ObjectId::new();
-        raffle.status = "created".to_string();
-        collection.insert_one(raffle, None).await
+        if raffle.status != "running" {
+            raffle.status = "created".to_string();
+        }
+        raffle.version = 0;
+        self.raffles()
+            .insert_one(&*raffle, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
     }
 
-    pub async fn insert_ticket(
-        &self,
-        client: &Client,
-        ticket: &mut Ticket,
-    ) -> Result<InsertOneResult, Error> {
+    async fn insert_ticket(&self, ticket: &mut Ticket) -> Result<(), Error> {
         ticket.date_created = chrono::Utc::now().timestamp();
         ticket.date_updated = chrono::Utc::now().timestamp();
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
-        collection.insert_one(ticket, None).await
+        ticket.version = 0;
+        self.tickets()
+            .insert_one(&*ticket, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+
+    /// A standalone MongoDB server has no multi-document transactions. Instead the raffle's
+    /// `tickets_sold` is advanced with a compare-and-swap before the ticket is inserted;
+    /// a concurrent allocation that lost the swap reads the new count and tries again.
+    async fn allocate_ticket(&self, ticket: &mut Ticket) -> Result<bool, Error> {
+        let requested = ticket.amount;
+        let (raffle, sold) = loop {
+            let raffle = self
+                .get_raffle_by_id(ticket.raffle_id)
+                .await?
+                .pop()
+                .context(RaffleNotFoundSnafu { id: ticket.raffle_id })?;
+            let sold = self.tickets_sold(ticket.raffle_id).await?;
+            ticket.amount = tickets_left(&raffle, sold, requested);
+            if ticket.amount == 0 {
+                return Ok(false);
+            }
+            let claimed = self
+                .raffle_documents()
+                .update_one(
+                    doc! {"_id": raffle.id, "tickets_sold": sold as i32},
+                    doc! {"$inc": {"tickets_sold": ticket.amount as i32}},
+                    None,
+                )
+                .await
+                .context(MongoSnafu)?;
+            if claimed.modified_count > 0 {
+                break (raffle, sold);
+            }
+        };
+
+        if let Err(err) = self.insert_ticket(ticket).await {
+            // Hand the claimed tickets back
+            self.add_tickets_sold(raffle.id, -(ticket.amount as i32)).await?;
+            return Err(err);
+        }
+
+        if sold + ticket.amount < raffle.ticket_amount {
+            return Ok(false);
+        }
+        let result = self
+            .raffles()
+            .update_one(
+                doc! {"_id": raffle.id, "status": {"$ne": "closed"}},
+                doc! {"$set": {"status": "closed"}, "$inc": {"version": 1i64}},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.modified_count > 0)
     }
     //endregion
 
     //region === REMOVE ===
-    pub async fn remove_raffle(
-        &self,
-        client: &Client,
-        raffle_id: ObjectId,
-    ) -> Result<DeleteResult, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Raffle>(COLL_RAFFLE.as_ref());
-        collection.delete_one(doc! {"_id": raffle_id}, None).await
+    async fn remove_raffle(&self, raffle_id: ObjectId) -> Result<u64, Error> {
+        let result = self
+            .raffles()
+            .delete_one(doc! {"_id": raffle_id}, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.deleted_count)
     }
 
-    pub async fn remove_ticket(
-        &self,
-        client: &Client,
-        ticket_id: ObjectId,
-    ) -> Result<DeleteResult, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
-        collection.delete_one(doc! {"_id": ticket_id}, None).await
+    async fn remove_ticket(&self, ticket_id: ObjectId) -> Result<u64, Error> {
+        let removed = self
+            .tickets()
+            .find_one_and_delete(doc! {"_id": ticket_id}, None)
+            .await
+            .context(MongoSnafu)?;
+        let Some(ticket) = removed else {
+            return Ok(0);
+        };
+        self.add_tickets_sold(ticket.raffle_id, -(ticket.amount as i32)).await?;
+        Ok(1)
     }
     //endregion
 
     //region === FIND ALL ===
-    pub async fn get_all_raffles(&self, client: &Client) -> Result<Vec<Raffle>, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Raffle>(COLL_RAFFLE.as_ref());
-        collection.find(None, None).await?.try_collect().await
+    async fn get_all_raffles(&self) -> Result<Vec<Raffle>, Error> {
+        let cursor = self.raffles().find(None, None).await.context(MongoSnafu)?;
+        cursor.try_collect().await.context(MongoSnafu)
     }
 
-    pub async fn get_all_tickets(&self, client: &Client) -> Result<Vec<Ticket>, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
-
-        collection.find(None, None).await?.try_collect().await
+    async fn get_all_tickets(&self) -> Result<Vec<Ticket>, Error> {
+        let cursor = self.tickets().find(None, None).await.context(MongoSnafu)?;
+        cursor.try_collect().await.context(MongoSnafu)
     }
     //endregion
 
-    //region === FIND BY ID ===
-    pub async fn get_raffle_by_id(
+    //region === FIND PAGED ===
+    async fn find_raffles(
         &self,
-        client: &Client,
-        id: ObjectId,
-    ) -> Result<Vec<Raffle>, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Raffle>(COLL_RAFFLE.as_ref());
-        collection
-            .find(doc! {"_id": id.clone()}, None)
-            .await?
-            .try_collect()
+        filter: &RaffleFilter,
+        page: PageRequest,
+    ) -> Result<Page<Raffle>, Error> {
+        let mut query = Document::new();
+        if let Some(status) = &filter.status {
+            query.insert("status", status);
+        }
+        if let Some(token) = &filter.token {
+            query.insert("ticket_token_name", token);
+        }
+        let options = page_query(&mut query, filter.date_from, filter.date_to, &page);
+        let cursor = self
+            .raffles()
+            .find(query, options)
             .await
+            .context(MongoSnafu)?;
+        let raffles = cursor.try_collect().await.context(MongoSnafu)?;
+        Ok(page.into_page(raffles, |r: &Raffle| r.id))
     }
 
-    pub async fn get_ticket_by_id(
+    async fn find_tickets(
         &self,
-        client: &Client,
-        id: ObjectId,
-    ) -> Result<Vec<Ticket>, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
-        collection
+        filter: &TicketFilter,
+        page: PageRequest,
+    ) -> Result<Page<Ticket>, Error> {
+        let mut query = Document::new();
+        if let Some(raffle_id) = filter.raffle_id {
+            query.insert("raffle_id", raffle_id);
+        }
+        if let Some(username) = &filter.username {
+            query.insert("username", username);
+        }
+        let options = page_query(&mut query, filter.date_from, filter.date_to, &page);
+        let cursor = self
+            .tickets()
+            .find(query, options)
+            .await
+            .context(MongoSnafu)?;
+        let tickets = cursor.try_collect().await.context(MongoSnafu)?;
+        Ok(page.into_page(tickets, |t: &Ticket| t.id))
+    }
+    //endregion
+
+    //region === FIND BY ID ===
+    async fn get_raffle_by_id(&self, id: ObjectId) -> Result<Vec<Raffle>, Error> {
+        let cursor = self
+            .raffles()
+            .find(doc! {"_id": id}, None)
+            .await
+            .context(MongoSnafu)?;
+        cursor.try_collect().await.context(MongoSnafu)
+    }
+
+    async fn get_ticket_by_id(&self, id: ObjectId) -> Result<Vec<Ticket>, Error> {
+        let cursor = self
+            .tickets()
             .find(doc! {"_id": id}, None)
-            .await?
-            .try_collect()
             .await
+            .context(MongoSnafu)?;
+        cursor.try_collect().await.context(MongoSnafu)
     }
     //endregion
 
     //region === FIND SPECIAL ===
-    pub async fn get_tickets_by_id_raffle(
-        &self,
-        client: &Client,
-        id: ObjectId,
-    ) -> Result<Vec<Ticket>, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
-        collection
+    async fn get_tickets_by_id_raffle(&self, id: ObjectId) -> Result<Vec<Ticket>, Error> {
+        let cursor = self
+            .tickets()
             .find(doc! {"raffle_id": id}, None)
-            .await?
-            .try_collect()
             .await
+            .context(MongoSnafu)?;
+        cursor.try_collect().await.context(MongoSnafu)
     }
 
-    pub async fn get_spl_tx_in_ticket(
-        &self,
-        client: &Client,
-        spl_tx_signature: &String,
-    ) -> Result<Option<Ticket>, Error> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
-
-        collection
+    async fn get_spl_tx_in_ticket(&self, spl_tx_signature: &str) -> Result<Option<Ticket>, Error> {
+        self.tickets()
             .find_one(doc! {"spl_tx_signature": spl_tx_signature}, None)
             .await
+            .context(MongoSnafu)
+    }
+
+    async fn get_tickets_sold(&self, status: &str) -> Result<Vec<(Raffle, u64)>, Error> {
+        let cursor = self
+            .raffles()
+            .find(doc! {"status": status}, None)
+            .await
+            .context(MongoSnafu)?;
+        let raffles: Vec<Raffle> = cursor.try_collect().await.context(MongoSnafu)?;
+        let ids: Vec<ObjectId> = raffles.iter().map(|raffle| raffle.id).collect();
+        let pipeline = [
+            doc! {"$match": {"raffle_id": {"$in": ids}}},
+            doc! {"$group": {"_id": "$raffle_id", "sold": {"$sum": "$amount"}}},
+        ];
+        let cursor = self
+            .tickets()
+            .aggregate(pipeline, None)
+            .await
+            .context(MongoSnafu)?;
+        let sums: Vec<Document> = cursor.try_collect().await.context(MongoSnafu)?;
+        Ok(raffles
+            .into_iter()
+            .map(|raffle| {
+                let sold = sums
+                    .iter()
+                    .find(|sum| sum.get_object_id("_id") == Ok(raffle.id))
+                    .and_then(|sum| match sum.get("sold") {
+                        Some(Bson::Int32(sold)) => Some(*sold as i64),
+                        Some(Bson::Int64(sold)) => Some(*sold),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                (raffle, sold.max(0) as u64)
+            })
+            .collect())
     }
     //endregion
 
     //region === UPDATE ===
-    pub async fn update_raffle(
-        &self,
-        client: &Client,
-        raffle: &mut Raffle,
-    ) -> mongodb::error::Result<UpdateResult> {
+    async fn update_raffle(&self, raffle: &mut Raffle) -> Result<u64, Error> {
         raffle.date_updated = chrono::Utc::now().timestamp();
 
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Raffle>(COLL_RAFFLE.as_ref());
-
         let r = raffle.clone();
+        let announcements = mongodb::bson::to_bson(&r.announcements).context(BsonSnafu)?;
+        let validation = mongodb::bson::to_bson(&r.validation).context(BsonSnafu)?;
         let doc = doc! {
                 "$set":{
                 "title": r.title,
                 "description": r.description,
                 "status": r.status,
                 "ticket_amount": r.ticket_amount as i32,
-                "ticket_price": r.ticket_price as f32,
+                "ticket_price": r.ticket_price,
                 "ticket_token_name": r.ticket_token_name,
                 "rule": r.rule,
-                "date_updated": chrono::Utc::now().timestamp()
-        }};
-        collection.update_one(doc! {"_id": r.id}, doc, None).await
+                "prizes": r.prizes,
+                "date_start": r.date_start,
+                "date_end": r.date_end,
+                "announcements": announcements,
+                "validation": validation,
+                "date_updated": r.date_updated
+        },
+                "$inc": {"version": 1i64}
+        };
+        let result = self
+            .raffles()
+            .update_one(doc! {"_id": r.id, "version": version_filter(r.version)}, doc, None)
+            .await
+            .context(MongoSnafu)?;
+        if result.matched_count > 0 {
+            raffle.version += 1;
+        }
+        Ok(result.matched_count)
     }
-    pub async fn update_ticket(
-        &self,
-        client: &Client,
-        ticket: &Ticket,
-    ) -> mongodb::error::Result<UpdateResult> {
-        let collection = client
-            .database(DB_NAME.as_ref())
-            .collection::<Ticket>(COLL_TICKET.as_ref());
 
+    async fn update_ticket(&self, ticket: &Ticket) -> Result<u64, Error> {
         let t = ticket.clone();
 
         let doc = doc! {
                 "$set":{
                 "username": t.username
-        }};
-        collection.update_one(doc! {"_id": t.id}, doc, None).await
+        },
+                "$inc": {"version": 1i64}
+        };
+        let result = self
+            .tickets()
+            .update_one(doc! {"_id": t.id, "version": version_filter(t.version)}, doc, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.matched_count)
+    }
+    //endregion
+
+    //region === IDEMPOTENCY ===
+    async fn claim_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let inserted = self
+            .idempotency()
+            .insert_one(
+                doc! {
+                    "_id": &record.key,
+                    "request_hash": &record.request_hash,
+                    "date_created": record.date_created,
+                },
+                None,
+            )
+            .await;
+        match inserted {
+            Ok(_) => return Ok(None),
+            Err(err) if is_duplicate_key(&err) => {}
+            Err(err) => return Err(err).context(MongoSnafu),
+        }
+
+        let Some(stored) = self
+            .idempotency()
+            .find_one(doc! {"_id": &record.key}, None)
+            .await
+            .context(MongoSnafu)?
+        else {
+            // Released in between, try once more
+            return self.claim_idempotency_key(record).await;
+        };
+        Ok(Some(IdempotencyRecord {
+            key: record.key.clone(),
+            request_hash: stored.get_str("request_hash").unwrap_or_default().to_string(),
+            response: stored.get_i64("status").ok().map(|status| StoredResponse {
+                status: status as u16,
+                content_type: stored.get_str("content_type").ok().map(str::to_string),
+                body: stored.get_str("body").unwrap_or_default().to_string(),
+            }),
+            date_created: stored.get_i64("date_created").unwrap_or_default(),
+        }))
+    }
+
+    async fn replace_idempotency_key(
+        &self,
+        stale: &IdempotencyRecord,
+        record: &IdempotencyRecord,
+    ) -> Result<bool, Error> {
+        let result = self
+            .idempotency()
+            .update_one(
+                claim_filter(stale),
+                doc! {
+                    "$set": {"request_hash": &record.request_hash, "date_created": record.date_created},
+                    "$unset": {"status": "", "content_type": "", "body": ""},
+                },
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        claim: &IdempotencyRecord,
+        response: &StoredResponse,
+    ) -> Result<(), Error> {
+        self.idempotency()
+            .update_one(
+                claim_filter(claim),
+                doc! {"$set": {
+                    "status": response.status as i64,
+                    "content_type": &response.content_type,
+                    "body": &response.body,
+                }},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, claim: &IdempotencyRecord) -> Result<(), Error> {
+        self.idempotency()
+            .delete_one(claim_filter(claim), None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+    //endregion
+
+    //region === API KEYS ===
+    async fn insert_api_key(&self, key: &StoredApiKey) -> Result<(), Error> {
+        self.api_keys()
+            .insert_one(key, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKeyInfo>, Error> {
+        let cursor = self.api_keys().find(None, None).await.context(MongoSnafu)?;
+        let keys: Vec<StoredApiKey> = cursor.try_collect().await.context(MongoSnafu)?;
+        Ok(keys.into_iter().map(|key| key.info).collect())
+    }
+
+    async fn get_api_key_by_id(&self, id: ObjectId) -> Result<Option<ApiKeyInfo>, Error> {
+        let key = self
+            .api_keys()
+            .find_one(doc! {"_id": id}, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(key.map(|key| key.info))
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<StoredApiKey>, Error> {
+        self.api_keys()
+            .find_one(doc! {"key_hash": key_hash}, None)
+            .await
+            .context(MongoSnafu)
+    }
+
+    async fn rotate_api_key(&self, id: ObjectId, key_hash: &str, prefix: &str) -> Result<u64, Error> {
+        let result = self
+            .api_keys()
+            .update_one(
+                doc! {"_id": id, "date_revoked": null},
+                doc! {"$set": {"key_hash": key_hash, "prefix": prefix}},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.matched_count)
+    }
+
+    async fn revoke_api_key(&self, id: ObjectId, date_revoked: i64) -> Result<u64, Error> {
+        let result = self
+            .api_keys()
+            .update_one(
+                doc! {"_id": id, "date_revoked": null},
+                doc! {"$set": {"date_revoked": date_revoked}},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.matched_count)
+    }
+
+    async fn touch_api_key(&self, id: ObjectId, date_last_used: i64) -> Result<(), Error> {
+        self.api_keys()
+            .update_one(
+                doc! {"_id": id},
+                doc! {"$set": {"date_last_used": date_last_used}},
+                None,
+            )
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+    //endregion
+
+    //region === AUDIT ===
+    async fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), Error> {
+        self.audit()
+            .insert_one(entry, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+
+    async fn find_audit_entries(
+        &self,
+        filter: &AuditFilter,
+        page: PageRequest,
+    ) -> Result<Page<AuditEntry>, Error> {
+        let mut query = Document::new();
+        if let Some(actor) = &filter.actor {
+            query.insert("actor", actor);
+        }
+        if let Some(action) = &filter.action {
+            query.insert("action", action);
+        }
+        if let Some(collection) = &filter.collection {
+            query.insert("collection", collection);
+        }
+        if let Some(document_id) = filter.document_id {
+            query.insert("document_id", document_id);
+        }
+        let options = page_query(&mut query, filter.date_from, filter.date_to, &page);
+        let cursor = self
+            .audit()
+            .find(query, options)
+            .await
+            .context(MongoSnafu)?;
+        let entries = cursor.try_collect().await.context(MongoSnafu)?;
+        Ok(page.into_page(entries, |e: &AuditEntry| e.id))
+    }
+    //endregion
+
+    //region === WEBHOOKS ===
+    async fn insert_webhook(&self, webhook: &StoredWebhook) -> Result<(), Error> {
+        self.webhooks()
+            .insert_one(webhook, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<StoredWebhook>, Error> {
+        let cursor = self.webhooks().find(None, None).await.context(MongoSnafu)?;
+        cursor.try_collect().await.context(MongoSnafu)
+    }
+
+    async fn remove_webhook(&self, id: ObjectId) -> Result<u64, Error> {
+        let result = self
+            .webhooks()
+            .delete_one(doc! {"_id": id}, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(result.deleted_count)
+    }
+
+    async fn insert_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        self.webhook_deliveries()
+            .insert_one(delivery, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
+    }
+
+    async fn find_webhook_deliveries(
+        &self,
+        webhook_id: ObjectId,
+        page: PageRequest,
+    ) -> Result<Page<WebhookDelivery>, Error> {
+        let mut query = doc! {"webhook_id": webhook_id};
+        let options = page_query(&mut query, None, None, &page);
+        let cursor = self
+            .webhook_deliveries()
+            .find(query, options)
+            .await
+            .context(MongoSnafu)?;
+        let deliveries = cursor.try_collect().await.context(MongoSnafu)?;
+        Ok(page.into_page(deliveries, |d: &WebhookDelivery| d.id))
+    }
+    //endregion
+
+    //region === HEALTH ===
+    async fn ping(&self) -> Result<(), Error> {
+        self.database()
+            .run_command(doc! {"ping": 1}, None)
+            .await
+            .context(MongoSnafu)?;
+        Ok(())
     }
     //endregion
 }
@@ -0,0 +1,232 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use crate::metrics::{DB_DURATION, DB_ERRORS, METRICS};
+use crate::model::{ApiKeyInfo, AuditEntry, Page, WebhookDelivery};
+use crate::repository::{
+    AuditFilter, Error, IdempotencyRecord, PageRequest, RaffleFilter, RaffleRepository, StoredApiKey,
+    StoredResponse, StoredWebhook, TicketFilter,
+};
+use crate::{ObjectId, Raffle, Ticket};
+
+/// Backend wrapper recording the duration and failures of every operation.
+pub struct TimedRepository {
+    inner: Arc<dyn RaffleRepository>,
+}
+
+impl TimedRepository {
+    pub fn new(inner: Arc<dyn RaffleRepository>) -> Self {
+        Self { inner }
+    }
+}
+
+async fn timed<T>(
+    operation: &'static str,
+    future: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let start = Instant::now();
+    let result = future.await;
+    let labels = vec![("operation", operation.to_string())];
+    METRICS.observe(DB_DURATION, labels.clone(), start.elapsed());
+    if result.is_err() {
+        METRICS.inc(DB_ERRORS, labels);
+    }
+    result
+}
+
+#[async_trait]
+impl RaffleRepository for TimedRepository {
+    //region === INSERT ===
+    async fn insert_raffle(&self, raffle: &mut Raffle) -> Result<(), Error> {
+        timed("insert_raffle", self.inner.insert_raffle(raffle)).await
+    }
+
+    async fn insert_ticket(&self, ticket: &mut Ticket) -> Result<(), Error> {
+        timed("insert_ticket", self.inner.insert_ticket(ticket)).await
+    }
+
+    async fn allocate_ticket(&self, ticket: &mut Ticket) -> Result<bool, Error> {
+        timed("allocate_ticket", self.inner.allocate_ticket(ticket)).await
+    }
+    //endregion
+
+    //region === REMOVE ===
+    async fn remove_raffle(&self, raffle_id: ObjectId) -> Result<u64, Error> {
+        timed("remove_raffle", self.inner.remove_raffle(raffle_id)).await
+    }
+
+    async fn remove_ticket(&self, ticket_id: ObjectId) -> Result<u64, Error> {
+        timed("remove_ticket", self.inner.remove_ticket(ticket_id)).await
+    }
+    //endregion
+
+    //region === FIND ALL ===
+    async fn get_all_raffles(&self) -> Result<Vec<Raffle>, Error> {
+        timed("get_all_raffles", self.inner.get_all_raffles()).await
+    }
+
+    async fn get_all_tickets(&self) -> Result<Vec<Ticket>, Error> {
+        timed("get_all_tickets", self.inner.get_all_tickets()).await
+    }
+    //endregion
+
+    //region === FIND PAGED ===
+    async fn find_raffles(
+        &self,
+        filter: &RaffleFilter,
+        page: PageRequest,
+    ) -> Result<Page<Raffle>, Error> {
+        timed("find_raffles", self.inner.find_raffles(filter, page)).await
+    }
+
+    async fn find_tickets(
+        &self,
+        filter: &TicketFilter,
+        page: PageRequest,
+    ) -> Result<Page<Ticket>, Error> {
+        timed("find_tickets", self.inner.find_tickets(filter, page)).await
+    }
+    //endregion
+
+    //region === FIND BY ID ===
+    async fn get_raffle_by_id(&self, id: ObjectId) -> Result<Vec<Raffle>, Error> {
+        timed("get_raffle_by_id", self.inner.get_raffle_by_id(id)).await
+    }
+
+    async fn get_ticket_by_id(&self, id: ObjectId) -> Result<Vec<Ticket>, Error> {
+        timed("get_ticket_by_id", self.inner.get_ticket_by_id(id)).await
+    }
+    //endregion
+
+    //region === FIND SPECIAL ===
+    async fn get_tickets_by_id_raffle(&self, id: ObjectId) -> Result<Vec<Ticket>, Error> {
+        timed("get_tickets_by_id_raffle", self.inner.get_tickets_by_id_raffle(id)).await
+    }
+
+    async fn get_spl_tx_in_ticket(&self, spl_tx_signature: &str) -> Result<Option<Ticket>, Error> {
+        timed("get_spl_tx_in_ticket", self.inner.get_spl_tx_in_ticket(spl_tx_signature)).await
+    }
+
+    async fn get_tickets_sold(&self, status: &str) -> Result<Vec<(Raffle, u64)>, Error> {
+        timed("get_tickets_sold", self.inner.get_tickets_sold(status)).await
+    }
+    //endregion
+
+    //region === UPDATE ===
+    async fn update_raffle(&self, raffle: &mut Raffle) -> Result<u64, Error> {
+        timed("update_raffle", self.inner.update_raffle(raffle)).await
+    }
+
+    async fn update_ticket(&self, ticket: &Ticket) -> Result<u64, Error> {
+        timed("update_ticket", self.inner.update_ticket(ticket)).await
+    }
+    //endregion
+
+    //region === IDEMPOTENCY ===
+    async fn claim_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        timed("claim_idempotency_key", self.inner.claim_idempotency_key(record)).await
+    }
+
+    async fn replace_idempotency_key(
+        &self,
+        stale: &IdempotencyRecord,
+        record: &IdempotencyRecord,
+    ) -> Result<bool, Error> {
+        timed("replace_idempotency_key", self.inner.replace_idempotency_key(stale, record)).await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        claim: &IdempotencyRecord,
+        response: &StoredResponse,
+    ) -> Result<(), Error> {
+        timed("complete_idempotency_key", self.inner.complete_idempotency_key(claim, response)).await
+    }
+
+    async fn release_idempotency_key(&self, claim: &IdempotencyRecord) -> Result<(), Error> {
+        timed("release_idempotency_key", self.inner.release_idempotency_key(claim)).await
+    }
+    //endregion
+
+    //region === API KEYS ===
+    async fn insert_api_key(&self, key: &StoredApiKey) -> Result<(), Error> {
+        timed("insert_api_key", self.inner.insert_api_key(key)).await
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKeyInfo>, Error> {
+        timed("get_api_keys", self.inner.get_api_keys()).await
+    }
+
+    async fn get_api_key_by_id(&self, id: ObjectId) -> Result<Option<ApiKeyInfo>, Error> {
+        timed("get_api_key_by_id", self.inner.get_api_key_by_id(id)).await
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<StoredApiKey>, Error> {
+        timed("get_api_key_by_hash", self.inner.get_api_key_by_hash(key_hash)).await
+    }
+
+    async fn rotate_api_key(&self, id: ObjectId, key_hash: &str, prefix: &str) -> Result<u64, Error> {
+        timed("rotate_api_key", self.inner.rotate_api_key(id, key_hash, prefix)).await
+    }
+
+    async fn revoke_api_key(&self, id: ObjectId, date_revoked: i64) -> Result<u64, Error> {
+        timed("revoke_api_key", self.inner.revoke_api_key(id, date_revoked)).await
+    }
+
+    async fn touch_api_key(&self, id: ObjectId, date_last_used: i64) -> Result<(), Error> {
+        timed("touch_api_key", self.inner.touch_api_key(id, date_last_used)).await
+    }
+    //endregion
+
+    //region === AUDIT ===
+    async fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), Error> {
+        timed("insert_audit_entry", self.inner.insert_audit_entry(entry)).await
+    }
+
+    async fn find_audit_entries(
+        &self,
+        filter: &AuditFilter,
+        page: PageRequest,
+    ) -> Result<Page<AuditEntry>, Error> {
+        timed("find_audit_entries", self.inner.find_audit_entries(filter, page)).await
+    }
+    //endregion
+
+    //region === WEBHOOKS ===
+    async fn insert_webhook(&self, webhook: &StoredWebhook) -> Result<(), Error> {
+        timed("insert_webhook", self.inner.insert_webhook(webhook)).await
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<StoredWebhook>, Error> {
+        timed("get_webhooks", self.inner.get_webhooks()).await
+    }
+
+    async fn remove_webhook(&self, id: ObjectId) -> Result<u64, Error> {
+        timed("remove_webhook", self.inner.remove_webhook(id)).await
+    }
+
+    async fn insert_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        timed("insert_webhook_delivery", self.inner.insert_webhook_delivery(delivery)).await
+    }
+
+    async fn find_webhook_deliveries(
+        &self,
+        webhook_id: ObjectId,
+        page: PageRequest,
+    ) -> Result<Page<WebhookDelivery>, Error> {
+        timed("find_webhook_deliveries", self.inner.find_webhook_deliveries(webhook_id, page)).await
+    }
+    //endregion
+
+    //region === HEALTH ===
+    async fn ping(&self) -> Result<(), Error> {
+        timed("ping", self.inner.ping()).await
+    }
+    //endregion
+}
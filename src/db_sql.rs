@@ -0,0 +1,851 @@
+use crate::model::{ApiKeyInfo, AuditEntry, Page, SortOrder, Webhook, WebhookDelivery};
+use crate::repository::{
+    tickets_left, AuditFilter, Error, IdempotencyRecord, StoredApiKey, InvalidStoredIdSnafu,
+    InvalidStoredValueSnafu, JsonSnafu, MigrateSnafu,
+    PageRequest, RaffleFilter, RaffleNotFoundSnafu, RaffleRepository, SqlSnafu, StoredResponse,
+    StoredWebhook, TicketFilter,
+};
+use crate::{ObjectId, Raffle, Ticket};
+use async_trait::async_trait;
+use snafu::prelude::*;
+use sqlx::any::{AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Row};
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+const RAFFLE_COLUMNS: &str = "id, title, description, status, ticket_amount, ticket_price, \
+    ticket_token_name, rule, date_created, date_updated, prizes, date_start, date_end, version, \
+    announcements, validation";
+const TICKET_COLUMNS: &str = "id, raffle_id, username, spl_tx_signature, amount_send, amount, \
+    date_created, date_updated, version";
+const API_KEY_COLUMNS: &str = "id, name, key_hash, prefix, scopes, date_created, date_expires, \
+    date_last_used, date_revoked";
+const WEBHOOK_COLUMNS: &str = "id, url, events, secret, date_created";
+const DELIVERY_COLUMNS: &str = "id, webhook_id, event_id, event, attempt, status, error, success, \
+    date_created";
+const AUDIT_COLUMNS: &str = "id, actor, action, route, collection, document_id, changes, date_created";
+
+/// PostgreSQL / SQLite backend, selected by the scheme of the connection URL.
+#[derive(Clone)]
+pub struct DatabaseSql {
+    pool: AnyPool,
+}
+
+impl DatabaseSql {
+    /// Connects to `url` (`postgres://...` or `sqlite://...`) and applies pending migrations.
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        sqlx::any::install_default_drivers();
+        // Every connection to an in-memory SQLite database opens a fresh, empty database.
+        let max_connections = if url.contains(":memory:") { 1 } else { 10 };
+        let pool = AnyPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await
+            .context(SqlSnafu)?;
+        MIGRATOR.run(&pool).await.context(MigrateSnafu)?;
+        Ok(Self { pool })
+    }
+
+    async fn fetch_raffles(&self, sql: &str, key: Option<String>) -> Result<Vec<Raffle>, Error> {
+        let mut query = sqlx::query(sql);
+        if let Some(key) = key {
+            query = query.bind(key);
+        }
+        let rows = query.fetch_all(&self.pool).await.context(SqlSnafu)?;
+        rows.iter().map(raffle_from_row).collect()
+    }
+
+    async fn fetch_tickets(&self, sql: &str, key: Option<String>) -> Result<Vec<Ticket>, Error> {
+        let mut query = sqlx::query(sql);
+        if let Some(key) = key {
+            query = query.bind(key);
+        }
+        let rows = query.fetch_all(&self.pool).await.context(SqlSnafu)?;
+        rows.iter().map(ticket_from_row).collect()
+    }
+}
+
+enum SqlArg {
+    Text(String),
+    Int(i64),
+}
+
+/// `WHERE` clause with numbered `$n` placeholders, which both Postgres and SQLite accept.
+#[derive(Default)]
+struct Conditions {
+    clauses: Vec<String>,
+    args: Vec<SqlArg>,
+}
+
+impl Conditions {
+    fn push(&mut self, column_op: &str, arg: SqlArg) {
+        self.args.push(arg);
+        self.clauses
+            .push(format!("{} ${}", column_op, self.args.len()));
+    }
+
+    fn date_range(&mut self, date_from: Option<i64>, date_to: Option<i64>) {
+        if let Some(from) = date_from {
+            self.push("date_created >=", SqlArg::Int(from));
+        }
+        if let Some(to) = date_to {
+            self.push("date_created <=", SqlArg::Int(to));
+        }
+    }
+
+    /// Appends the cursor condition, order and limit of `page` and returns the full query.
+    fn paged_query(mut self, select: &str, page: &PageRequest) -> (String, Vec<SqlArg>) {
+        let (cursor_op, direction) = match page.sort {
+            SortOrder::Asc => ("id >", "ASC"),
+            SortOrder::Desc => ("id <", "DESC"),
+        };
+        if let Some(cursor) = page.cursor {
+            self.push(cursor_op, SqlArg::Text(cursor.to_hex()));
+        }
+        let mut sql = select.to_string();
+        if !self.clauses.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.clauses.join(" AND "));
+        }
+        self.args.push(SqlArg::Int(page.fetch_limit()));
+        sql.push_str(&format!(" ORDER BY id {} LIMIT ${}", direction, self.args.len()));
+        (sql, self.args)
+    }
+}
+
+async fn fetch_paged(pool: &AnyPool, sql: &str, args: Vec<SqlArg>) -> Result<Vec<AnyRow>, Error> {
+    let mut query = sqlx::query(sql);
+    for arg in args {
+        query = match arg {
+            SqlArg::Text(value) => query.bind(value),
+            SqlArg::Int(value) => query.bind(value),
+        };
+    }
+    query.fetch_all(pool).await.context(SqlSnafu)
+}
+
+fn parse_id(value: String) -> Result<ObjectId, Error> {
+    ObjectId::parse_str(&value)
+        .ok()
+        .context(InvalidStoredIdSnafu { value })
+}
+
+/// Ticket counts are stored as BIGINT; a value out of range is reported, not truncated.
+fn to_u16(value: i64) -> Result<u16, Error> {
+    u16::try_from(value)
+        .ok()
+        .context(InvalidStoredValueSnafu { value: value.to_string() })
+}
+
+fn raffle_from_row(row: &AnyRow) -> Result<Raffle, Error> {
+    Ok(Raffle {
+        id: parse_id(row.try_get("id").context(SqlSnafu)?)?,
+        title: row.try_get("title").context(SqlSnafu)?,
+        description: row.try_get("description").context(SqlSnafu)?,
+        status: row.try_get("status").context(SqlSnafu)?,
+        ticket_amount: to_u16(row.try_get("ticket_amount").context(SqlSnafu)?)?,
+        ticket_price: row.try_get::<f64, _>("ticket_price").context(SqlSnafu)? as f32,
+        ticket_token_name: row.try_get("ticket_token_name").context(SqlSnafu)?,
+        rule: row.try_get("rule").context(SqlSnafu)?,
+        date_created: row.try_get("date_created").context(SqlSnafu)?,
+        date_updated: row.try_get("date_updated").context(SqlSnafu)?,
+        prizes: serde_json::from_str(&row.try_get::<String, _>("prizes").context(SqlSnafu)?)
+            .context(JsonSnafu)?,
+        date_start: row.try_get("date_start").context(SqlSnafu)?,
+        date_end: row.try_get("date_end").context(SqlSnafu)?,
+        version: row.try_get("version").context(SqlSnafu)?,
+        announcements: serde_json::from_str(
+            &row.try_get::<String, _>("announcements").context(SqlSnafu)?,
+        )
+        .context(JsonSnafu)?,
+        validation: serde_json::from_str(&row.try_get::<String, _>("validation").context(SqlSnafu)?)
+            .context(JsonSnafu)?,
+    })
+}
+
+fn ticket_from_row(row: &AnyRow) -> Result<Ticket, Error> {
+    Ok(Ticket {
+        id: parse_id(row.try_get("id").context(SqlSnafu)?)?,
+        raffle_id: parse_id(row.try_get("raffle_id").context(SqlSnafu)?)?,
+        username: row.try_get("username").context(SqlSnafu)?,
+        spl_tx_signature: row.try_get("spl_tx_signature").context(SqlSnafu)?,
+        amount_send: row.try_get::<f64, _>("amount_send").context(SqlSnafu)? as f32,
+        amount: to_u16(row.try_get("amount").context(SqlSnafu)?)?,
+        date_created: row.try_get("date_created").context(SqlSnafu)?,
+        date_updated: row.try_get("date_updated").context(SqlSnafu)?,
+        version: row.try_get("version").context(SqlSnafu)?,
+    })
+}
+
+fn api_key_from_row(row: &AnyRow) -> Result<StoredApiKey, Error> {
+    Ok(StoredApiKey {
+        info: ApiKeyInfo {
+            id: parse_id(row.try_get("id").context(SqlSnafu)?)?,
+            name: row.try_get("name").context(SqlSnafu)?,
+            scopes: serde_json::from_str(&row.try_get::<String, _>("scopes").context(SqlSnafu)?)
+                .context(JsonSnafu)?,
+            prefix: row.try_get("prefix").context(SqlSnafu)?,
+            date_created: row.try_get("date_created").context(SqlSnafu)?,
+            date_expires: row.try_get("date_expires").context(SqlSnafu)?,
+            date_last_used: row.try_get("date_last_used").context(SqlSnafu)?,
+            date_revoked: row.try_get("date_revoked").context(SqlSnafu)?,
+        },
+        key_hash: row.try_get("key_hash").context(SqlSnafu)?,
+    })
+}
+
+fn audit_entry_from_row(row: &AnyRow) -> Result<AuditEntry, Error> {
+    Ok(AuditEntry {
+        id: parse_id(row.try_get("id").context(SqlSnafu)?)?,
+        actor: row.try_get("actor").context(SqlSnafu)?,
+        action: row.try_get("action").context(SqlSnafu)?,
+        route: row.try_get("route").context(SqlSnafu)?,
+        collection: row.try_get("collection").context(SqlSnafu)?,
+        document_id: parse_id(row.try_get("document_id").context(SqlSnafu)?)?,
+        changes: serde_json::from_str(&row.try_get::<String, _>("changes").context(SqlSnafu)?)
+            .context(JsonSnafu)?,
+        date_created: row.try_get("date_created").context(SqlSnafu)?,
+    })
+}
+
+fn webhook_from_row(row: &AnyRow) -> Result<StoredWebhook, Error> {
+    Ok(StoredWebhook {
+        info: Webhook {
+            id: parse_id(row.try_get("id").context(SqlSnafu)?)?,
+            url: row.try_get("url").context(SqlSnafu)?,
+            events: serde_json::from_str(&row.try_get::<String, _>("events").context(SqlSnafu)?)
+                .context(JsonSnafu)?,
+            date_created: row.try_get("date_created").context(SqlSnafu)?,
+        },
+        secret: row.try_get("secret").context(SqlSnafu)?,
+    })
+}
+
+fn delivery_from_row(row: &AnyRow) -> Result<WebhookDelivery, Error> {
+    let event: String = row.try_get("event").context(SqlSnafu)?;
+    Ok(WebhookDelivery {
+        id: parse_id(row.try_get("id").context(SqlSnafu)?)?,
+        webhook_id: parse_id(row.try_get("webhook_id").context(SqlSnafu)?)?,
+        event_id: parse_id(row.try_get("event_id").context(SqlSnafu)?)?,
+        event: event
+            .parse()
+            .ok()
+            .context(InvalidStoredValueSnafu { value: event })?,
+        attempt: row.try_get::<i64, _>("attempt").context(SqlSnafu)? as u32,
+        status: row
+            .try_get::<Option<i64>, _>("status")
+            .context(SqlSnafu)?
+            .map(|status| status as u16),
+        error: row.try_get("error").context(SqlSnafu)?,
+        success: row.try_get::<i64, _>("success").context(SqlSnafu)? != 0,
+        date_created: row.try_get("date_created").context(SqlSnafu)?,
+    })
+}
+
+async fn insert_ticket_row<'c, E>(executor: E, ticket: &Ticket) -> Result<(), Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Any>,
+{
+    sqlx::query(&format!(
+        "INSERT INTO ticket ({TICKET_COLUMNS}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+    ))
+    .bind(ticket.id.to_hex())
+    .bind(ticket.raffle_id.to_hex())
+    .bind(ticket.username.clone())
+    .bind(ticket.spl_tx_signature.clone())
+    .bind(ticket.amount_send as f64)
+    .bind(ticket.amount as i64)
+    .bind(ticket.date_created)
+    .bind(ticket.date_updated)
+    .bind(ticket.version)
+    .execute(executor)
+    .await
+    .context(SqlSnafu)?;
+    Ok(())
+}
+
+#[async_trait]
+impl RaffleRepository for DatabaseSql {
+    //region === INSERT ===
+    async fn insert_raffle(&self, raffle: &mut Raffle) -> Result<(), Error> {
+        raffle.date_created = chrono::Utc::now().timestamp();
+        raffle.date_updated = chrono::Utc::now().timestamp();
+        raffle.id = ObjectId::new();
+        if raffle.status != "running" {
+            raffle.status = "created".to_string();
+        }
+        raffle.version = 0;
+        sqlx::query(&format!(
+            "INSERT INTO raffle ({RAFFLE_COLUMNS}) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)"
+        ))
+        .bind(raffle.id.to_hex())
+        .bind(raffle.title.clone())
+        .bind(raffle.description.clone())
+        .bind(raffle.status.clone())
+        .bind(raffle.ticket_amount as i64)
+        .bind(raffle.ticket_price as f64)
+        .bind(raffle.ticket_token_name.clone())
+        .bind(raffle.rule.clone())
+        .bind(raffle.date_created)
+        .bind(raffle.date_updated)
+        .bind(serde_json::to_string(&raffle.prizes).context(JsonSnafu)?)
+        .bind(raffle.date_start)
+        .bind(raffle.date_end)
+        .bind(raffle.version)
+        .bind(serde_json::to_string(&raffle.announcements).context(JsonSnafu)?)
+        .bind(serde_json::to_string(&raffle.validation).context(JsonSnafu)?)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(())
+    }
+
+    async fn insert_ticket(&self, ticket: &mut Ticket) -> Result<(), Error> {
+        ticket.date_created = chrono::Utc::now().timestamp();
+        ticket.date_updated = chrono::Utc::now().timestamp();
+        ticket.version = 0;
+        insert_ticket_row(&self.pool, ticket).await
+    }
+
+    async fn allocate_ticket(&self, ticket: &mut Ticket) -> Result<bool, Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await.context(SqlSnafu)?;
+
+        // Writing the raffle row first takes its lock (row lock on Postgres, write lock on
+        // SQLite), so concurrent allocations for the same raffle are serialized. The write
+        // changes nothing, like the Mongo backend a sale leaves `date_updated` alone.
+        let locked = sqlx::query("UPDATE raffle SET id = id WHERE id = $1")
+            .bind(ticket.raffle_id.to_hex())
+            .execute(&mut *tx)
+            .await
+            .context(SqlSnafu)?;
+        ensure!(
+            locked.rows_affected() > 0,
+            RaffleNotFoundSnafu { id: ticket.raffle_id }
+        );
+
+        let row = sqlx::query(&format!("SELECT {RAFFLE_COLUMNS} FROM raffle WHERE id = $1"))
+            .bind(ticket.raffle_id.to_hex())
+            .fetch_one(&mut *tx)
+            .await
+            .context(SqlSnafu)?;
+        let raffle = raffle_from_row(&row)?;
+        let sold: i64 = sqlx::query_scalar(
+            "SELECT CAST(COALESCE(SUM(amount), 0) AS BIGINT) FROM ticket WHERE raffle_id = $1",
+        )
+        .bind(ticket.raffle_id.to_hex())
+        .fetch_one(&mut *tx)
+        .await
+        .context(SqlSnafu)?;
+        let sold = u16::try_from(sold).unwrap_or(u16::MAX);
+
+        ticket.amount = tickets_left(&raffle, sold, ticket.amount);
+        if ticket.amount == 0 {
+            tx.rollback().await.context(SqlSnafu)?;
+            return Ok(false);
+        }
+        ticket.date_created = now;
+        ticket.date_updated = now;
+        ticket.version = 0;
+        insert_ticket_row(&mut *tx, ticket).await?;
+
+        let mut closed = false;
+        if sold + ticket.amount >= raffle.ticket_amount {
+            let result = sqlx::query(
+                "UPDATE raffle SET status = 'closed', version = version + 1 WHERE id = $1 AND status <> 'closed'",
+            )
+            .bind(ticket.raffle_id.to_hex())
+            .execute(&mut *tx)
+            .await
+            .context(SqlSnafu)?;
+            closed = result.rows_affected() > 0;
+        }
+        tx.commit().await.context(SqlSnafu)?;
+        Ok(closed)
+    }
+    //endregion
+
+    //region === REMOVE ===
+    async fn remove_raffle(&self, raffle_id: ObjectId) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM raffle WHERE id = $1")
+            .bind(raffle_id.to_hex())
+            .execute(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        Ok(result.rows_affected())
+    }
+
+    async fn remove_ticket(&self, ticket_id: ObjectId) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM ticket WHERE id = $1")
+            .bind(ticket_id.to_hex())
+            .execute(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        Ok(result.rows_affected())
+    }
+    //endregion
+
+    //region === FIND ALL ===
+    async fn get_all_raffles(&self) -> Result<Vec<Raffle>, Error> {
+        self.fetch_raffles(&format!("SELECT {RAFFLE_COLUMNS} FROM raffle"), None)
+            .await
+    }
+
+    async fn get_all_tickets(&self) -> Result<Vec<Ticket>, Error> {
+        self.fetch_tickets(&format!("SELECT {TICKET_COLUMNS} FROM ticket"), None)
+            .await
+    }
+    //endregion
+
+    //region === FIND PAGED ===
+    async fn find_raffles(
+        &self,
+        filter: &RaffleFilter,
+        page: PageRequest,
+    ) -> Result<Page<Raffle>, Error> {
+        let mut conditions = Conditions::default();
+        if let Some(status) = &filter.status {
+            conditions.push("status =", SqlArg::Text(status.clone()));
+        }
+        if let Some(token) = &filter.token {
+            conditions.push("ticket_token_name =", SqlArg::Text(token.clone()));
+        }
+        conditions.date_range(filter.date_from, filter.date_to);
+        let (sql, args) =
+            conditions.paged_query(&format!("SELECT {RAFFLE_COLUMNS} FROM raffle"), &page);
+        let rows = fetch_paged(&self.pool, &sql, args).await?;
+        let raffles = rows.iter().map(raffle_from_row).collect::<Result<_, _>>()?;
+        Ok(page.into_page(raffles, |r: &Raffle| r.id))
+    }
+
+    async fn find_tickets(
+        &self,
+        filter: &TicketFilter,
+        page: PageRequest,
+    ) -> Result<Page<Ticket>, Error> {
+        let mut conditions = Conditions::default();
+        if let Some(raffle_id) = filter.raffle_id {
+            conditions.push("raffle_id =", SqlArg::Text(raffle_id.to_hex()));
+        }
+        if let Some(username) = &filter.username {
+            conditions.push("username =", SqlArg::Text(username.clone()));
+        }
+        conditions.date_range(filter.date_from, filter.date_to);
+        let (sql, args) =
+            conditions.paged_query(&format!("SELECT {TICKET_COLUMNS} FROM ticket"), &page);
+        let rows = fetch_paged(&self.pool, &sql, args).await?;
+        let tickets = rows.iter().map(ticket_from_row).collect::<Result<_, _>>()?;
+        Ok(page.into_page(tickets, |t: &Ticket| t.id))
+    }
+    //endregion
+
+    //region === FIND BY ID ===
+    async fn get_raffle_by_id(&self, id: ObjectId) -> Result<Vec<Raffle>, Error> {
+        self.fetch_raffles(
+            &format!("SELECT {RAFFLE_COLUMNS} FROM raffle WHERE id = $1"),
+            Some(id.to_hex()),
+        )
+        .await
+    }
+
+    async fn get_ticket_by_id(&self, id: ObjectId) -> Result<Vec<Ticket>, Error> {
+        self.fetch_tickets(
+            &format!("SELECT {TICKET_COLUMNS} FROM ticket WHERE id = $1"),
+            Some(id.to_hex()),
+        )
+        .await
+    }
+    //endregion
+
+    //region === FIND SPECIAL ===
+    async fn get_tickets_by_id_raffle(&self, id: ObjectId) -> Result<Vec<Ticket>, Error> {
+        self.fetch_tickets(
+            &format!("SELECT {TICKET_COLUMNS} FROM ticket WHERE raffle_id = $1"),
+            Some(id.to_hex()),
+        )
+        .await
+    }
+
+    async fn get_spl_tx_in_ticket(&self, spl_tx_signature: &str) -> Result<Option<Ticket>, Error> {
+        let mut tickets = self
+            .fetch_tickets(
+                &format!("SELECT {TICKET_COLUMNS} FROM ticket WHERE spl_tx_signature = $1 LIMIT 1"),
+                Some(spl_tx_signature.to_string()),
+            )
+            .await?;
+        Ok(tickets.pop())
+    }
+
+    async fn get_tickets_sold(&self, status: &str) -> Result<Vec<(Raffle, u64)>, Error> {
+        let rows = sqlx::query(&format!(
+            "SELECT {RAFFLE_COLUMNS}, (SELECT CAST(COALESCE(SUM(amount), 0) AS BIGINT) FROM ticket \
+             WHERE ticket.raffle_id = raffle.id) AS tickets_sold FROM raffle WHERE status = $1"
+        ))
+        .bind(status.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        rows.iter()
+            .map(|row| {
+                let sold: i64 = row.try_get("tickets_sold").context(SqlSnafu)?;
+                Ok((raffle_from_row(row)?, sold.max(0) as u64))
+            })
+            .collect()
+    }
+    //endregion
+
+    //region === UPDATE ===
+    async fn update_raffle(&self, raffle: &mut Raffle) -> Result<u64, Error> {
+        raffle.date_updated = chrono::Utc::now().timestamp();
+        let result = sqlx::query(
+            "UPDATE raffle SET title = $1, description = $2, status = $3, ticket_amount = $4, \
+             ticket_price = $5, ticket_token_name = $6, rule = $7, prizes = $8, date_start = $9, \
+             date_end = $10, date_updated = $11, announcements = $12, validation = $13, \
+             version = version + 1 WHERE id = $14 AND version = $15",
+        )
+        .bind(raffle.title.clone())
+        .bind(raffle.description.clone())
+        .bind(raffle.status.clone())
+        .bind(raffle.ticket_amount as i64)
+        .bind(raffle.ticket_price as f64)
+        .bind(raffle.ticket_token_name.clone())
+        .bind(raffle.rule.clone())
+        .bind(serde_json::to_string(&raffle.prizes).context(JsonSnafu)?)
+        .bind(raffle.date_start)
+        .bind(raffle.date_end)
+        .bind(raffle.date_updated)
+        .bind(serde_json::to_string(&raffle.announcements).context(JsonSnafu)?)
+        .bind(serde_json::to_string(&raffle.validation).context(JsonSnafu)?)
+        .bind(raffle.id.to_hex())
+        .bind(raffle.version)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        if result.rows_affected() > 0 {
+            raffle.version += 1;
+        }
+        Ok(result.rows_affected())
+    }
+
+    async fn update_ticket(&self, ticket: &Ticket) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE ticket SET username = $1, version = version + 1 WHERE id = $2 AND version = $3",
+        )
+            .bind(ticket.username.clone())
+            .bind(ticket.id.to_hex())
+            .bind(ticket.version)
+            .execute(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        Ok(result.rows_affected())
+    }
+    //endregion
+
+    //region === IDEMPOTENCY ===
+    async fn claim_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error> {
+        let inserted = sqlx::query(
+            "INSERT INTO idempotency (idempotency_key, request_hash, date_created) \
+             VALUES ($1, $2, $3) ON CONFLICT (idempotency_key) DO NOTHING",
+        )
+        .bind(record.key.clone())
+        .bind(record.request_hash.clone())
+        .bind(record.date_created)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        if inserted.rows_affected() > 0 {
+            return Ok(None);
+        }
+
+        let row = sqlx::query(
+            "SELECT idempotency_key, request_hash, status, content_type, body, date_created \
+             FROM idempotency WHERE idempotency_key = $1",
+        )
+        .bind(record.key.clone())
+        .fetch_optional(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        let Some(row) = row else {
+            // Released in between, try once more
+            return self.claim_idempotency_key(record).await;
+        };
+        let status: Option<i64> = row.try_get("status").context(SqlSnafu)?;
+        Ok(Some(IdempotencyRecord {
+            key: row.try_get("idempotency_key").context(SqlSnafu)?,
+            request_hash: row.try_get("request_hash").context(SqlSnafu)?,
+            response: match status {
+                Some(status) => Some(StoredResponse {
+                    status: status as u16,
+                    content_type: row.try_get("content_type").context(SqlSnafu)?,
+                    body: row.try_get("body").context(SqlSnafu)?,
+                }),
+                None => None,
+            },
+            date_created: row.try_get("date_created").context(SqlSnafu)?,
+        }))
+    }
+
+    async fn replace_idempotency_key(
+        &self,
+        stale: &IdempotencyRecord,
+        record: &IdempotencyRecord,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            "UPDATE idempotency SET request_hash = $1, date_created = $2, status = NULL, \
+             content_type = NULL, body = NULL \
+             WHERE idempotency_key = $3 AND request_hash = $4 AND date_created = $5",
+        )
+        .bind(record.request_hash.clone())
+        .bind(record.date_created)
+        .bind(stale.key.clone())
+        .bind(stale.request_hash.clone())
+        .bind(stale.date_created)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        claim: &IdempotencyRecord,
+        response: &StoredResponse,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "UPDATE idempotency SET status = $1, content_type = $2, body = $3 \
+             WHERE idempotency_key = $4 AND request_hash = $5 AND date_created = $6",
+        )
+        .bind(response.status as i64)
+        .bind(response.content_type.clone())
+        .bind(response.body.clone())
+        .bind(claim.key.clone())
+        .bind(claim.request_hash.clone())
+        .bind(claim.date_created)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, claim: &IdempotencyRecord) -> Result<(), Error> {
+        sqlx::query(
+            "DELETE FROM idempotency \
+             WHERE idempotency_key = $1 AND request_hash = $2 AND date_created = $3",
+        )
+        .bind(claim.key.clone())
+        .bind(claim.request_hash.clone())
+        .bind(claim.date_created)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(())
+    }
+    //endregion
+
+    //region === API KEYS ===
+    async fn insert_api_key(&self, key: &StoredApiKey) -> Result<(), Error> {
+        sqlx::query(&format!(
+            "INSERT INTO api_key ({API_KEY_COLUMNS}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+        ))
+        .bind(key.info.id.to_hex())
+        .bind(key.info.name.clone())
+        .bind(key.key_hash.clone())
+        .bind(key.info.prefix.clone())
+        .bind(serde_json::to_string(&key.info.scopes).context(JsonSnafu)?)
+        .bind(key.info.date_created)
+        .bind(key.info.date_expires)
+        .bind(key.info.date_last_used)
+        .bind(key.info.date_revoked)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(())
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKeyInfo>, Error> {
+        let rows = sqlx::query(&format!("SELECT {API_KEY_COLUMNS} FROM api_key ORDER BY id"))
+            .fetch_all(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        rows.iter()
+            .map(|row| api_key_from_row(row).map(|key| key.info))
+            .collect()
+    }
+
+    async fn get_api_key_by_id(&self, id: ObjectId) -> Result<Option<ApiKeyInfo>, Error> {
+        let row = sqlx::query(&format!("SELECT {API_KEY_COLUMNS} FROM api_key WHERE id = $1"))
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        row.as_ref()
+            .map(|row| api_key_from_row(row).map(|key| key.info))
+            .transpose()
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<StoredApiKey>, Error> {
+        let row = sqlx::query(&format!("SELECT {API_KEY_COLUMNS} FROM api_key WHERE key_hash = $1"))
+            .bind(key_hash.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        row.as_ref().map(api_key_from_row).transpose()
+    }
+
+    async fn rotate_api_key(&self, id: ObjectId, key_hash: &str, prefix: &str) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE api_key SET key_hash = $1, prefix = $2 WHERE id = $3 AND date_revoked IS NULL",
+        )
+        .bind(key_hash.to_string())
+        .bind(prefix.to_string())
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(result.rows_affected())
+    }
+
+    async fn revoke_api_key(&self, id: ObjectId, date_revoked: i64) -> Result<u64, Error> {
+        let result =
+            sqlx::query("UPDATE api_key SET date_revoked = $1 WHERE id = $2 AND date_revoked IS NULL")
+                .bind(date_revoked)
+                .bind(id.to_hex())
+                .execute(&self.pool)
+                .await
+                .context(SqlSnafu)?;
+        Ok(result.rows_affected())
+    }
+
+    async fn touch_api_key(&self, id: ObjectId, date_last_used: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE api_key SET date_last_used = $1 WHERE id = $2")
+            .bind(date_last_used)
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        Ok(())
+    }
+    //endregion
+
+    //region === AUDIT ===
+    async fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), Error> {
+        sqlx::query(&format!(
+            "INSERT INTO audit_log ({AUDIT_COLUMNS}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+        ))
+        .bind(entry.id.to_hex())
+        .bind(entry.actor.clone())
+        .bind(entry.action.clone())
+        .bind(entry.route.clone())
+        .bind(entry.collection.clone())
+        .bind(entry.document_id.to_hex())
+        .bind(serde_json::to_string(&entry.changes).context(JsonSnafu)?)
+        .bind(entry.date_created)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(())
+    }
+
+    async fn find_audit_entries(
+        &self,
+        filter: &AuditFilter,
+        page: PageRequest,
+    ) -> Result<Page<AuditEntry>, Error> {
+        let mut conditions = Conditions::default();
+        if let Some(actor) = &filter.actor {
+            conditions.push("actor =", SqlArg::Text(actor.clone()));
+        }
+        if let Some(action) = &filter.action {
+            conditions.push("action =", SqlArg::Text(action.clone()));
+        }
+        if let Some(collection) = &filter.collection {
+            conditions.push("collection =", SqlArg::Text(collection.clone()));
+        }
+        if let Some(document_id) = filter.document_id {
+            conditions.push("document_id =", SqlArg::Text(document_id.to_hex()));
+        }
+        conditions.date_range(filter.date_from, filter.date_to);
+        let (sql, args) =
+            conditions.paged_query(&format!("SELECT {AUDIT_COLUMNS} FROM audit_log"), &page);
+        let rows = fetch_paged(&self.pool, &sql, args).await?;
+        let entries = rows.iter().map(audit_entry_from_row).collect::<Result<_, _>>()?;
+        Ok(page.into_page(entries, |e: &AuditEntry| e.id))
+    }
+    //endregion
+
+    //region === WEBHOOKS ===
+    async fn insert_webhook(&self, webhook: &StoredWebhook) -> Result<(), Error> {
+        sqlx::query(&format!("INSERT INTO webhook ({WEBHOOK_COLUMNS}) VALUES ($1, $2, $3, $4, $5)"))
+            .bind(webhook.info.id.to_hex())
+            .bind(webhook.info.url.clone())
+            .bind(serde_json::to_string(&webhook.info.events).context(JsonSnafu)?)
+            .bind(webhook.secret.clone())
+            .bind(webhook.info.date_created)
+            .execute(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        Ok(())
+    }
+
+    async fn get_webhooks(&self) -> Result<Vec<StoredWebhook>, Error> {
+        let rows = sqlx::query(&format!("SELECT {WEBHOOK_COLUMNS} FROM webhook ORDER BY id"))
+            .fetch_all(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        rows.iter().map(webhook_from_row).collect()
+    }
+
+    async fn remove_webhook(&self, id: ObjectId) -> Result<u64, Error> {
+        let result = sqlx::query("DELETE FROM webhook WHERE id = $1")
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await
+            .context(SqlSnafu)?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        sqlx::query(&format!(
+            "INSERT INTO webhook_delivery ({DELIVERY_COLUMNS}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+        ))
+        .bind(delivery.id.to_hex())
+        .bind(delivery.webhook_id.to_hex())
+        .bind(delivery.event_id.to_hex())
+        .bind(delivery.event.as_str())
+        .bind(delivery.attempt as i64)
+        .bind(delivery.status.map(i64::from))
+        .bind(delivery.error.clone())
+        .bind(delivery.success as i64)
+        .bind(delivery.date_created)
+        .execute(&self.pool)
+        .await
+        .context(SqlSnafu)?;
+        Ok(())
+    }
+
+    async fn find_webhook_deliveries(
+        &self,
+        webhook_id: ObjectId,
+        page: PageRequest,
+    ) -> Result<Page<WebhookDelivery>, Error> {
+        let mut conditions = Conditions::default();
+        conditions.push("webhook_id =", SqlArg::Text(webhook_id.to_hex()));
+        let (sql, args) = conditions
+            .paged_query(&format!("SELECT {DELIVERY_COLUMNS} FROM webhook_delivery"), &page);
+        let rows = fetch_paged(&self.pool, &sql, args).await?;
+        let deliveries = rows.iter().map(delivery_from_row).collect::<Result<_, _>>()?;
+        Ok(page.into_page(deliveries, |d: &WebhookDelivery| d.id))
+    }
+    //endregion
+
+    //region === HEALTH ===
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await.context(SqlSnafu)?;
+        Ok(())
+    }
+    //endregion
+}
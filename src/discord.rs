@@ -0,0 +1,306 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::EventBus;
+use crate::model::*;
+use crate::repository::RaffleRepository;
+use crate::settings::DiscordSettings;
+
+/// Discord rejects embeds with longer titles or descriptions.
+const MAX_TITLE: usize = 256;
+const MAX_DESCRIPTION: usize = 4096;
+
+/// Posts raffle events as embeds to Discord webhook URLs.
+pub struct Announcer {
+    urls: Vec<String>,
+    events: Vec<EventKind>,
+    http: reqwest::Client,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Embed {
+    pub title: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+}
+
+impl Announcer {
+    pub fn new(urls: Vec<String>, events: Vec<EventKind>) -> Self {
+        Self {
+            urls,
+            events,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("HTTP client can be built"),
+        }
+    }
+
+    /// `None` if no webhook URL is configured; no event types announce all of them.
+    pub fn from_settings(settings: &DiscordSettings) -> Option<Self> {
+        if settings.webhook_urls.is_empty() {
+            return None;
+        }
+        let events = if settings.events.is_empty() {
+            EventKind::ALL.to_vec()
+        } else {
+            settings.events.clone()
+        };
+        Some(Self::new(settings.webhook_urls.clone(), events))
+    }
+
+    /// Announces every event published on `bus`, each in its own task so a slow webhook
+    /// does not hold up the events after it.
+    pub fn spawn(self, db_interface: Arc<dyn RaffleRepository>, bus: &EventBus) {
+        info!("Announcing raffle events to {} Discord webhooks", self.urls.len());
+        let announcer = Arc::new(self);
+        let mut events = bus.subscribe();
+        actix_web::rt::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Discord announcer fell behind, {} events were not announced", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if announcer.events.contains(&event.kind) {
+                    let (announcer, db_interface) = (announcer.clone(), db_interface.clone());
+                    actix_web::rt::spawn(async move {
+                        announcer.announce(db_interface.as_ref(), &event).await;
+                    });
+                }
+            }
+        });
+    }
+
+    /// Posts `event` to every URL at once, failures are logged and not retried.
+    pub async fn announce(&self, db_interface: &dyn RaffleRepository, event: &Event) {
+        if !self.events.contains(&event.kind) {
+            return;
+        }
+        let (raffle, ticket) = match event.kind {
+            EventKind::TicketCreated => {
+                match db_interface.get_raffle_by_id(event.raffle_id).await {
+                    Ok(raffles) => (raffles.into_iter().next(), Some(&event.data)),
+                    Err(err) => {
+                        warn!("Could not load raffle {} to announce: {}", event.raffle_id, err);
+                        return;
+                    }
+                }
+            }
+            _ => (serde_json::from_value(event.data.clone()).ok(), None),
+        };
+        let Some(raffle) = raffle else {
+            warn!("Raffle {} of {} is gone, not announcing", event.raffle_id, event.kind.as_str());
+            return;
+        };
+        let embed = render(event.kind, &raffle, ticket);
+        let body = serde_json::to_vec(&serde_json::json!({ "embeds": [embed] })).unwrap_or_default();
+        let posts = self.urls.iter().map(|url| {
+            self.http
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .send()
+        });
+        for result in futures::future::join_all(posts).await {
+            match result.and_then(|response| response.error_for_status()) {
+                Ok(_) => {}
+                Err(err) => warn!("Discord announcement of {} failed: {}", event.kind.as_str(), err),
+            }
+        }
+    }
+}
+
+/// The embed of `kind` from the raffle's template for it, or the built-in one.
+pub fn render(kind: EventKind, raffle: &Raffle, ticket: Option<&Value>) -> Embed {
+    let template = raffle
+        .announcements
+        .get(kind.as_str())
+        .cloned()
+        .unwrap_or_else(|| default_template(kind));
+    let raffle = serde_json::to_value(raffle).unwrap_or_default();
+    let fill = |text: &str| fill(text, &raffle, ticket.unwrap_or(&Value::Null));
+    Embed {
+        title: truncate(fill(&template.title), MAX_TITLE),
+        description: truncate(fill(&template.description), MAX_DESCRIPTION),
+        color: template.color,
+    }
+}
+
+fn default_template(kind: EventKind) -> EmbedTemplate {
+    let (title, description, color) = match kind {
+        EventKind::TicketCreated => (
+            "Ticket bought for {raffle.title}",
+            "{ticket.username} bought {ticket.amount} tickets.",
+            0x5865F2,
+        ),
+        EventKind::RaffleOpened => (
+            "{raffle.title} is open",
+            "{raffle.description}\n\n{raffle.ticket_amount} tickets at \
+             {raffle.ticket_price} {raffle.ticket_token_name}.",
+            0x57F287,
+        ),
+        EventKind::RaffleSoldOut => (
+            "{raffle.title} is sold out",
+            "All {raffle.ticket_amount} tickets are sold.",
+            0xFEE75C,
+        ),
+        EventKind::RaffleClosed => ("{raffle.title} is closed", "No more tickets are sold.", 0xED4245),
+        EventKind::RaffleDrawn => ("{raffle.title} is drawn", "The winners have been drawn.", 0xEB459E),
+    };
+    EmbedTemplate {
+        title: title.to_string(),
+        description: description.to_string(),
+        color: Some(color),
+    }
+}
+
+/// Replaces `{raffle.<field>}` and `{ticket.<field>}`; unknown placeholders are kept.
+fn fill(text: &str, raffle: &Value, ticket: &Value) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else { break };
+        let placeholder = &rest[1..end];
+        let value = match placeholder.split_once('.') {
+            Some(("raffle", field)) => raffle.get(field),
+            Some(("ticket", field)) => ticket.get(field),
+            _ => None,
+        };
+        match value {
+            Some(Value::String(value)) => filled.push_str(value),
+            Some(Value::Null) | None => filled.push_str(&rest[..=end]),
+            Some(value) => filled.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+fn truncate(mut text: String, max_chars: usize) -> String {
+    if let Some((index, _)) = text.char_indices().nth(max_chars) {
+        text.truncate(index);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::*;
+    use crate::db_sql::DatabaseSql;
+
+    fn raffle() -> Raffle {
+        Raffle {
+            id: ObjectId::new(),
+            title: "Summer".to_string(),
+            description: String::new(),
+            status: "running".to_string(),
+            ticket_amount: 10,
+            ticket_price: 1.5,
+            ticket_token_name: "USDC".to_string(),
+            rule: String::new(),
+            prizes: vec![],
+            date_start: None,
+            date_end: None,
+            announcements: Default::default(),
+            validation: Default::default(),
+            date_created: 0,
+            date_updated: 0,
+            version: 0,
+        }
+    }
+
+    #[test]
+    fn templates_are_filled() {
+        let mut raffle = raffle();
+        let embed = render(EventKind::RaffleSoldOut, &raffle, None);
+        assert_eq!(embed.title, "Summer is sold out");
+        assert_eq!(embed.description, "All 10 tickets are sold.");
+
+        raffle.announcements.insert(
+            "ticket.created".to_string(),
+            EmbedTemplate {
+                title: "{ticket.username} joined {raffle.title} {raffle.nope} {oops".to_string(),
+                description: "{raffle.ticket_price} {raffle.ticket_token_name}".to_string(),
+                color: None,
+            },
+        );
+        let ticket = serde_json::json!({ "username": "alice" });
+        let embed = render(EventKind::TicketCreated, &raffle, Some(&ticket));
+        assert_eq!(
+            embed,
+            Embed {
+                title: "alice joined Summer {raffle.nope} {oops".to_string(),
+                description: "1.5 USDC".to_string(),
+                color: None,
+            }
+        );
+        assert_eq!(truncate("é".repeat(300), MAX_TITLE).chars().count(), MAX_TITLE);
+    }
+
+    #[actix_web::test]
+    async fn events_are_posted_as_embeds() {
+        let received = web::Data::new(Mutex::new(Vec::<Value>::new()));
+        let receiver = {
+            let received = received.clone();
+            HttpServer::new(move || {
+                App::new().app_data(received.clone()).route(
+                    "/discord",
+                    web::post().to(
+                        |body: web::Json<Value>, received: web::Data<Mutex<Vec<Value>>>| async move {
+                            received.lock().unwrap().push(body.into_inner());
+                            HttpResponse::NoContent().finish()
+                        },
+                    ),
+                )
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap()
+        };
+        let address = receiver.addrs()[0];
+        actix_web::rt::spawn(receiver.run());
+
+        let db = DatabaseSql::connect("sqlite::memory:").await.unwrap();
+        let mut raffle = raffle();
+        db.insert_raffle(&mut raffle).await.unwrap();
+        let announcer = Announcer::new(
+            vec![format!("http://{}/discord", address)],
+            vec![EventKind::TicketCreated],
+        );
+        let event = |kind, data| Event {
+            id: ObjectId::new(),
+            kind,
+            raffle_id: raffle.id,
+            data,
+            date_created: 0,
+        };
+
+        let ticket = serde_json::json!({ "username": "bob", "amount": 2 });
+        announcer.announce(&db, &event(EventKind::TicketCreated, ticket)).await;
+        let drawn = serde_json::to_value(&raffle).unwrap();
+        announcer.announce(&db, &event(EventKind::RaffleDrawn, drawn)).await;
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["embeds"][0]["title"], "Ticket bought for Summer");
+        assert_eq!(received[0]["embeds"][0]["description"], "bob bought 2 tickets.");
+        assert_eq!(received[0]["embeds"][0]["color"], 0x5865F2);
+    }
+}
@@ -0,0 +1,506 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use log::{info, warn};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::api::store_ticket;
+use crate::auth::{ApiKey, Scope};
+use crate::error::ApiError;
+use crate::events::EventBus;
+use crate::model::*;
+use crate::rate_limit::RateLimiter;
+use crate::repository::{tickets_left, PageRequest, RaffleFilter, RaffleRepository, TicketFilter};
+use crate::settings::{DiscordSettings, Settings};
+
+pub const HEADER_SIGNATURE: &str = "X-Signature-Ed25519";
+pub const HEADER_TIMESTAMP: &str = "X-Signature-Timestamp";
+
+/// Interaction and response types of the Discord API.
+const PING: u8 = 1;
+const APPLICATION_COMMAND: u8 = 2;
+const PONG: u8 = 1;
+const CHANNEL_MESSAGE: u8 = 4;
+const DEFERRED_CHANNEL_MESSAGE: u8 = 5;
+/// Only the user who ran the command sees the answer.
+const EPHEMERAL: u32 = 1 << 6;
+
+/// Signed interactions older (or newer) than this are rejected, so captured ones can't be replayed.
+const MAX_TIMESTAMP_AGE_SECS: i64 = 300;
+
+/// Raffles listed by `/raffle list`, a message holds at most 2000 characters.
+const LIST_LIMIT: u32 = 10;
+
+/// Verifies and answers Discord interactions for the application owning `public_key`.
+pub struct Interactions {
+    public_key: Vec<u8>,
+    /// Base URL of the Discord API the answers of deferred commands are sent to.
+    api_url: String,
+    http: reqwest::Client,
+}
+
+impl Interactions {
+    pub fn new(public_key: Vec<u8>, api_url: String) -> Self {
+        Self {
+            public_key,
+            api_url,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("HTTP client can be built"),
+        }
+    }
+
+    /// `None` if no public key is configured; the settings checked that it is hex.
+    pub fn from_settings(settings: &DiscordSettings) -> Option<Self> {
+        let public_key = hex::decode(settings.public_key.as_deref()?).ok()?;
+        Some(Self::new(public_key, settings.api_url.clone()))
+    }
+
+    /// Whether `signature` (hex) signs `timestamp` followed by `body` and `timestamp` is recent.
+    pub fn verify(&self, signature: &str, timestamp: &str, body: &[u8]) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        let recent = timestamp.parse::<i64>().is_ok_and(|timestamp| {
+            (chrono::Utc::now().timestamp() - timestamp).abs() <= MAX_TIMESTAMP_AGE_SECS
+        });
+        if !recent {
+            return false;
+        }
+        let message = [timestamp.as_bytes(), body].concat();
+        UnparsedPublicKey::new(&ED25519, &self.public_key)
+            .verify(&message, &signature)
+            .is_ok()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    application_id: String,
+    token: String,
+    #[serde(default)]
+    data: Option<CommandData>,
+    /// Set in guilds, `user` in direct messages.
+    #[serde(default)]
+    member: Option<Member>,
+    #[serde(default)]
+    user: Option<User>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandData {
+    name: String,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandOption {
+    name: String,
+    #[serde(default)]
+    value: Option<Value>,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+impl CommandOption {
+    fn string(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|option| option.name == name)
+            .and_then(|option| option.value.as_ref()?.as_str())
+    }
+}
+
+/// Handles the `/raffle list`, `/raffle tickets` and `/raffle buy tx:<signature> [raffle:<id>]`
+/// slash commands. Requests must be signed by Discord, bearer tokens are not used.
+#[post("/discord/interactions")]
+#[allow(clippy::too_many_arguments)]
+pub async fn interact(
+    interactions: web::Data<Interactions>,
+    db_interface: web::Data<dyn RaffleRepository>,
+    event_bus: web::Data<EventBus>,
+    settings: web::Data<Settings>,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let signed = match (header(HEADER_SIGNATURE), header(HEADER_TIMESTAMP)) {
+        (Some(signature), Some(timestamp)) => interactions.verify(signature, timestamp, &body),
+        _ => false,
+    };
+    if !signed {
+        return Err(ApiError::Unauthorized);
+    }
+    let interaction: Interaction =
+        serde_json::from_slice(&body).map_err(|err| ApiError::InvalidRequest {
+            message: format!("Json deserialize error: {}", err),
+        })?;
+    if interaction.kind == PING {
+        return Ok(HttpResponse::Ok().json(json!({ "type": PONG })));
+    }
+
+    let username = interaction
+        .member
+        .as_ref()
+        .map(|member| &member.user)
+        .or(interaction.user.as_ref())
+        .map(|user| user.username.clone());
+    let subcommand = interaction
+        .data
+        .as_ref()
+        .filter(|data| interaction.kind == APPLICATION_COMMAND && data.name == "raffle")
+        .and_then(|data| data.options.first());
+    let (Some(username), Some(subcommand)) = (username, subcommand) else {
+        return Ok(message("Unknown command, try `/raffle list`."));
+    };
+    info!("Discord user {} ran /raffle {}", username, subcommand.name);
+
+    let content = match subcommand.name.as_str() {
+        "list" => list_raffles(db_interface.as_ref()).await?,
+        "tickets" => list_tickets(db_interface.as_ref(), &username).await?,
+        "buy" => {
+            let Some(tx) = subcommand.string("tx") else {
+                return Ok(message("`tx` is required."));
+            };
+            let raffle_id = match subcommand.string("raffle") {
+                Some(id) => match ObjectId::parse_str(id) {
+                    Ok(id) => id,
+                    Err(_) => return Ok(message(&format!("`{}` is not a raffle id.", id))),
+                },
+                None => match running_raffles(db_interface.as_ref(), 2).await?.as_slice() {
+                    [raffle] => raffle.id,
+                    [] => return Ok(message("No raffle is running.")),
+                    _ => return Ok(message("Several raffles are running, pick one with `raffle`.")),
+                },
+            };
+            if let Err(err) = rate_limiter.check_user(&username) {
+                return Ok(message(&format!("Your ticket was rejected: {}", err)));
+            }
+            let ticket = Ticket {
+                id: ObjectId::new(),
+                raffle_id,
+                username: username.clone(),
+                spl_tx_signature: tx.to_string(),
+                amount_send: 0.0,
+                amount: 0,
+                date_created: 0,
+                date_updated: 0,
+                version: 0,
+            };
+            // Checking the transaction may take longer than the 3 seconds Discord waits
+            actix_web::rt::spawn(async move {
+                let content = buy(db_interface.as_ref(), &event_bus, &settings, &req, ticket).await;
+                follow_up(&interactions, &interaction, &content).await;
+            });
+            return Ok(HttpResponse::Ok().json(json!({
+                "type": DEFERRED_CHANNEL_MESSAGE,
+                "data": { "flags": EPHEMERAL },
+            })));
+        }
+        _ => "Unknown command, try `/raffle list`.".to_string(),
+    };
+    Ok(message(&content))
+}
+
+fn message(content: &str) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "type": CHANNEL_MESSAGE,
+        "data": { "content": content, "flags": EPHEMERAL },
+    }))
+}
+
+async fn running_raffles(db_interface: &dyn RaffleRepository, limit: u32) -> Result<Vec<Raffle>, ApiError> {
+    let filter = RaffleFilter {
+        status: Some("running".to_string()),
+        ..Default::default()
+    };
+    let page = PageRequest::new(Some(limit), None, SortOrder::Desc);
+    Ok(db_interface.find_raffles(&filter, page).await?.items)
+}
+
+async fn list_raffles(db_interface: &dyn RaffleRepository) -> Result<String, ApiError> {
+    // One aggregate query, Discord only waits 3 seconds for the answer
+    let mut raffles = db_interface.get_tickets_sold("running").await?;
+    if raffles.is_empty() {
+        return Ok("No raffle is running.".to_string());
+    }
+    // Newest first, like `running_raffles`
+    raffles.sort_by_key(|(raffle, _)| std::cmp::Reverse(raffle.id));
+    let mut lines = Vec::new();
+    for (raffle, sold) in raffles.into_iter().take(LIST_LIMIT as usize) {
+        let sold = u16::try_from(sold).unwrap_or(u16::MAX);
+        lines.push(format!(
+            "**{}** `{}`: {} of {} tickets left at {} {}",
+            raffle.title,
+            raffle.id.to_hex(),
+            tickets_left(&raffle, sold, raffle.ticket_amount),
+            raffle.ticket_amount,
+            raffle.ticket_price,
+            raffle.ticket_token_name
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+async fn list_tickets(db_interface: &dyn RaffleRepository, username: &str) -> Result<String, ApiError> {
+    let filter = TicketFilter {
+        username: Some(username.to_string()),
+        ..Default::default()
+    };
+    let page = PageRequest::new(Some(LIST_LIMIT), None, SortOrder::Desc);
+    let tickets = db_interface.find_tickets(&filter, page).await?.items;
+    if tickets.is_empty() {
+        return Ok("You have no tickets yet.".to_string());
+    }
+    let mut lines = Vec::new();
+    for ticket in tickets {
+        let title = db_interface
+            .get_raffle_by_id(ticket.raffle_id)
+            .await?
+            .pop()
+            .map_or_else(|| ticket.raffle_id.to_hex(), |raffle| raffle.title);
+        lines.push(format!("**{}**: {} tickets", title, ticket.amount));
+    }
+    Ok(lines.join("\n"))
+}
+
+async fn buy(
+    db_interface: &dyn RaffleRepository,
+    event_bus: &EventBus,
+    settings: &Settings,
+    req: &HttpRequest,
+    mut ticket: Ticket,
+) -> String {
+    let key = ApiKey {
+        name: format!("discord:{}", ticket.username),
+        scopes: vec![Scope::TicketSubmit],
+    };
+    match store_ticket(db_interface, event_bus, settings, &key, req, &mut ticket).await {
+        Ok(()) => format!("You got {} Tickets", ticket.amount),
+        Err(err) => format!("Your ticket was rejected: {}", err),
+    }
+}
+
+/// Replaces the deferred answer to `interaction` with `content`.
+async fn follow_up(interactions: &Interactions, interaction: &Interaction, content: &str) {
+    let url = format!(
+        "{}/webhooks/{}/{}/messages/@original",
+        interactions.api_url, interaction.application_id, interaction.token
+    );
+    let body = serde_json::to_vec(&json!({ "content": content })).unwrap_or_default();
+    let result = interactions
+        .http
+        .patch(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await;
+    if let Err(err) = result.and_then(|response| response.error_for_status()) {
+        warn!("Could not answer Discord interaction: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{App, HttpServer};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+    use crate::db_sql::DatabaseSql;
+    use crate::rate_limit::Limit;
+
+    fn key_pair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    fn signed(key_pair: &Ed25519KeyPair, body: &Value) -> TestRequest {
+        let body = serde_json::to_vec(body).unwrap();
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signature = key_pair.sign(&[timestamp.as_bytes(), &body].concat());
+        TestRequest::post()
+            .uri("/discord/interactions")
+            .insert_header((HEADER_SIGNATURE, hex::encode(signature.as_ref())))
+            .insert_header((HEADER_TIMESTAMP, timestamp))
+            .set_payload(body)
+    }
+
+    fn command(subcommand: &str, options: Value) -> Value {
+        json!({
+            "type": APPLICATION_COMMAND,
+            "application_id": "app",
+            "token": "token",
+            "member": { "user": { "username": "alice" } },
+            "data": { "name": "raffle", "options": [ { "name": subcommand, "type": 1, "options": options } ] },
+        })
+    }
+
+    #[actix_web::test]
+    async fn commands_are_verified_and_answered() {
+        let received = web::Data::new(Mutex::new(Vec::<(String, Value)>::new()));
+        let discord = {
+            let received = received.clone();
+            HttpServer::new(move || {
+                App::new().app_data(received.clone()).route(
+                    "/webhooks/{application}/{token}/messages/@original",
+                    web::patch().to(
+                        |req: HttpRequest,
+                         body: web::Json<Value>,
+                         received: web::Data<Mutex<Vec<(String, Value)>>>| async move {
+                            received.lock().unwrap().push((req.path().to_string(), body.into_inner()));
+                            HttpResponse::Ok().finish()
+                        },
+                    ),
+                )
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap()
+        };
+        let address = discord.addrs()[0];
+        actix_web::rt::spawn(discord.run());
+
+        let key_pair = key_pair();
+        let db = Arc::new(DatabaseSql::connect("sqlite::memory:").await.unwrap());
+        let interactions = web::Data::new(Interactions::new(
+            key_pair.public_key().as_ref().to_vec(),
+            format!("http://{}", address),
+        ));
+        let limit = Limit {
+            requests: 1,
+            window: Duration::from_secs(60),
+        };
+        let rate_limiter = Arc::new(RateLimiter::new(None, Some(limit)));
+        let app = init_service(
+            App::new()
+                .app_data(interactions.clone())
+                .app_data(web::Data::from(db.clone() as Arc<dyn RaffleRepository>))
+                .app_data(web::Data::new(EventBus::default()))
+                .app_data(web::Data::new(Settings::default()))
+                .app_data(web::Data::new(rate_limiter.clone()))
+                .service(interact),
+        )
+        .await;
+
+        let ping = json!({ "type": PING, "application_id": "app", "token": "token" });
+        let res = call_service(&app, signed(&key_pair, &ping).to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(read_body_json::<Value, _>(res).await, json!({ "type": PONG }));
+
+        let forged = signed(&key_pair, &ping).set_payload(r#"{"type": 1, "forged": true}"#);
+        assert_eq!(call_service(&app, forged.to_request()).await.status(), 401);
+        let unsigned = TestRequest::post().uri("/discord/interactions").set_json(&ping);
+        assert_eq!(call_service(&app, unsigned.to_request()).await.status(), 401);
+        let body = serde_json::to_vec(&ping).unwrap();
+        let timestamp = (chrono::Utc::now().timestamp() - MAX_TIMESTAMP_AGE_SECS - 1).to_string();
+        let signature = key_pair.sign(&[timestamp.as_bytes(), &body].concat());
+        let replayed = TestRequest::post()
+            .uri("/discord/interactions")
+            .insert_header((HEADER_SIGNATURE, hex::encode(signature.as_ref())))
+            .insert_header((HEADER_TIMESTAMP, timestamp))
+            .set_payload(body);
+        assert_eq!(call_service(&app, replayed.to_request()).await.status(), 401);
+
+        let mut raffle = Raffle {
+            id: ObjectId::new(),
+            title: "Summer".to_string(),
+            description: String::new(),
+            status: String::new(),
+            ticket_amount: 10,
+            ticket_price: 1.0,
+            ticket_token_name: "USDC".to_string(),
+            rule: String::new(),
+            prizes: vec![],
+            date_start: None,
+            date_end: None,
+            announcements: Default::default(),
+            validation: Default::default(),
+            date_created: 0,
+            date_updated: 0,
+            version: 0,
+        };
+        db.insert_raffle(&mut raffle).await.unwrap();
+        raffle.status = "running".to_string();
+        db.update_raffle(&mut raffle).await.unwrap();
+
+        let list = signed(&key_pair, &command("list", json!([])));
+        let body: Value = read_body_json(call_service(&app, list.to_request()).await).await;
+        assert_eq!(body["type"], CHANNEL_MESSAGE);
+        assert_eq!(body["data"]["flags"], EPHEMERAL);
+        let content = body["data"]["content"].as_str().unwrap();
+        assert!(content.starts_with("**Summer**"), "{}", content);
+        assert!(content.contains("10 of 10 tickets left at 1 USDC"), "{}", content);
+
+        // Raw inserts aren't capped, their sum must not overflow
+        for _ in 0..2 {
+            let mut ticket = Ticket {
+                id: ObjectId::new(),
+                raffle_id: raffle.id,
+                username: "bob".to_string(),
+                spl_tx_signature: "sig".to_string(),
+                amount_send: 0.0,
+                amount: u16::MAX,
+                date_created: 0,
+                date_updated: 0,
+                version: 0,
+            };
+            db.insert_ticket(&mut ticket).await.unwrap();
+        }
+        let list = signed(&key_pair, &command("list", json!([])));
+        let body: Value = read_body_json(call_service(&app, list.to_request()).await).await;
+        let content = body["data"]["content"].as_str().unwrap();
+        assert!(content.contains("0 of 10 tickets left"), "{}", content);
+
+        let tickets = signed(&key_pair, &command("tickets", json!([])));
+        let body: Value = read_body_json(call_service(&app, tickets.to_request()).await).await;
+        assert_eq!(body["data"]["content"], "You have no tickets yet.");
+
+        let invalid = command("buy", json!([{ "name": "tx", "value": "sig" }, { "name": "raffle", "value": "x" }]));
+        let body: Value = read_body_json(call_service(&app, signed(&key_pair, &invalid).to_request()).await).await;
+        assert_eq!(body["data"]["content"], "`x` is not a raffle id.");
+
+        raffle.status = "drawn".to_string();
+        db.update_raffle(&mut raffle).await.unwrap();
+        let buy = command("buy", json!([{ "name": "tx", "value": "sig" }]));
+        let body: Value = read_body_json(call_service(&app, signed(&key_pair, &buy).to_request()).await).await;
+        assert_eq!(body["data"]["content"], "No raffle is running.");
+
+        rate_limiter.check_user("alice").unwrap();
+        let limited = command("buy", json!([{ "name": "tx", "value": "sig" }, { "name": "raffle", "value": raffle.id.to_hex() }]));
+        let body: Value = read_body_json(call_service(&app, signed(&key_pair, &limited).to_request()).await).await;
+        let content = body["data"]["content"].as_str().unwrap();
+        assert!(content.starts_with("Your ticket was rejected"), "{}", content);
+
+        let interaction: Interaction = serde_json::from_value(buy).unwrap();
+        follow_up(&interactions, &interaction, "You got 2 Tickets").await;
+        assert_eq!(
+            received.lock().unwrap().pop().unwrap(),
+            (
+                "/webhooks/app/token/messages/@original".to_string(),
+                json!({ "content": "You got 2 Tickets" })
+            )
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use actix_web::error::{JsonPayloadError, QueryPayloadError};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use log::error;
+use mongodb::bson::oid;
+use snafu::prelude::*;
+
+use crate::model::{ErrorBody, Violation};
+use crate::auth::Scope;
+use crate::{repository, validator};
+
+/// Error returned by the API handlers, rendered as an [`ErrorBody`].
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum ApiError {
+    #[snafu(display("{message}"))]
+    InvalidRequest { message: String },
+    #[snafu(display("Invalid id: {source}"))]
+    InvalidId { source: oid::Error },
+    #[snafu(display("Raffle is invalid: {} violation(s)", violations.len()))]
+    InvalidRaffle { violations: Vec<Violation> },
+    #[snafu(display("Patch changes immutable fields"))]
+    ImmutableField { violations: Vec<Violation> },
+    #[snafu(display("Patch names unknown fields"))]
+    UnknownField { violations: Vec<Violation> },
+    #[snafu(display("Missing or invalid API key"))]
+    Unauthorized,
+    #[snafu(display("API key lacks the '{scope}' scope"))]
+    Forbidden { scope: Scope },
+    #[snafu(display("Document changed since it was read"))]
+    VersionConflict,
+    #[snafu(display("Idempotency-Key was already used for a different request"))]
+    IdempotencyKeyReused,
+    #[snafu(display("A request with this Idempotency-Key is still in progress"))]
+    RequestInProgress,
+    #[snafu(display("Too many requests, retry in {retry_after} seconds"))]
+    RateLimited { retry_after: u64 },
+    #[snafu(display("Raffle does not exist"))]
+    RaffleNotFound,
+    #[snafu(display("Ticket does not exist"))]
+    TicketNotFound,
+    #[snafu(display("No active API key with this id"))]
+    ApiKeyNotFound,
+    #[snafu(display("No webhook with this id"))]
+    WebhookNotFound,
+    #[snafu(display("API key name '{name}' is taken or reserved"))]
+    ApiKeyNameTaken { name: String },
+    #[snafu(display("{source}"))]
+    TicketRejected { source: validator::Error },
+    #[snafu(display("{source}"))]
+    Storage { source: repository::Error },
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest { .. } => "invalid_request",
+            ApiError::InvalidId { .. } => "invalid_id",
+            ApiError::InvalidRaffle { .. } => "invalid_raffle",
+            ApiError::ImmutableField { .. } => "immutable_field",
+            ApiError::UnknownField { .. } => "unknown_field",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden { .. } => "forbidden",
+            ApiError::VersionConflict => "version_conflict",
+            ApiError::IdempotencyKeyReused => "idempotency_key_reused",
+            ApiError::RequestInProgress => "request_in_progress",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::RaffleNotFound => "raffle_not_found",
+            ApiError::TicketNotFound => "ticket_not_found",
+            ApiError::ApiKeyNotFound => "api_key_not_found",
+            ApiError::WebhookNotFound => "webhook_not_found",
+            ApiError::ApiKeyNameTaken { .. } => "api_key_name_taken",
+            ApiError::TicketRejected { source } => source.code(),
+            ApiError::Storage {
+                source: repository::Error::RaffleNotFound { .. },
+            } => "raffle_not_found",
+            ApiError::Storage { .. } => "storage_error",
+        }
+    }
+}
+
+impl From<validator::Error> for ApiError {
+    fn from(source: validator::Error) -> Self {
+        ApiError::TicketRejected { source }
+    }
+}
+
+impl From<repository::Error> for ApiError {
+    fn from(source: repository::Error) -> Self {
+        ApiError::Storage { source }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest { .. } | ApiError::InvalidId { .. } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidRaffle { .. }
+            | ApiError::ImmutableField { .. }
+            | ApiError::UnknownField { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden { .. } => StatusCode::FORBIDDEN,
+            ApiError::VersionConflict
+            | ApiError::RequestInProgress
+            | ApiError::ApiKeyNameTaken { .. } => StatusCode::CONFLICT,
+            ApiError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::RaffleNotFound
+            | ApiError::TicketNotFound
+            | ApiError::ApiKeyNotFound
+            | ApiError::WebhookNotFound => StatusCode::NOT_FOUND,
+            ApiError::TicketRejected { source } => source.status_code(),
+            ApiError::Storage {
+                source: repository::Error::RaffleNotFound { .. },
+            } => StatusCode::NOT_FOUND,
+            ApiError::Storage { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            error!("{:?}", self);
+        }
+        let mut builder = HttpResponse::build(self.status_code());
+        if let ApiError::RateLimited { retry_after } = self {
+            builder.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+        builder.json(ErrorBody {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            violations: match self {
+                ApiError::InvalidRaffle { violations }
+                | ApiError::ImmutableField { violations }
+                | ApiError::UnknownField { violations } => violations.clone(),
+                _ => Vec::new(),
+            },
+        })
+    }
+}
+
+/// Renders rejected JSON bodies as [`ErrorBody`], see `web::JsonConfig::error_handler`.
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::InvalidRequest {
+        message: err.to_string(),
+    }
+    .into()
+}
+
+/// Renders rejected query strings as [`ErrorBody`], see `web::QueryConfig::error_handler`.
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::InvalidRequest {
+        message: err.to_string(),
+    }
+    .into()
+}
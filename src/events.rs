@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::{get, web, HttpResponse};
+use futures::stream;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::IntoParams;
+
+use crate::auth::{ApiKey, Scope};
+use crate::error::{ApiError, InvalidIdSnafu};
+use crate::model::{ErrorBody, Event, EventKind, ObjectId, Raffle};
+
+/// Events buffered for a slow subscriber before it starts missing some.
+const CAPACITY: usize = 1024;
+
+/// An idle event stream sends a comment this often, so proxies keep it open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// In-process fan-out of raffle events; handlers publish, webhooks subscribe.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish<T: Serialize>(&self, kind: EventKind, raffle_id: ObjectId, data: &T) {
+        let event = Event {
+            id: ObjectId::new(),
+            kind,
+            raffle_id,
+            data: serde_json::to_value(data).unwrap_or_default(),
+            date_created: chrono::Utc::now().timestamp(),
+        };
+        debug!("Publishing {} for raffle {}", kind.as_str(), raffle_id);
+        // Fails only when nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+/// The event of an update that moved `before` into the `running`, `closed` or `drawn` status.
+pub fn status_change(before: &Raffle, after: &Raffle) -> Option<EventKind> {
+    if before.status == after.status {
+        return None;
+    }
+    match after.status.as_str() {
+        "running" => Some(EventKind::RaffleOpened),
+        "closed" => Some(EventKind::RaffleClosed),
+        "drawn" => Some(EventKind::RaffleDrawn),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventQuery {
+    /// Only events of this raffle
+    raffle_id: Option<String>,
+}
+
+#[utoipa::path(
+    tag = "events",
+    params(EventQuery),
+    responses(
+        (status = 200, description = "Server-Sent Events: `id` is the event id, `event` its type \
+            and `data` the JSON `Event`", body = Event, content_type = "text/event-stream"),
+        (status = 400, description = "Invalid id", body = ErrorBody),
+    ),
+)]
+#[get("/events")]
+pub async fn stream_events(
+    key: ApiKey,
+    event_bus: web::Data<EventBus>,
+    query: web::Query<EventQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let raffle_id = query
+        .raffle_id
+        .as_deref()
+        .map(ObjectId::parse_str)
+        .transpose()
+        .context(InvalidIdSnafu)?;
+    let events = stream::unfold(event_bus.subscribe(), move |mut events| async move {
+        loop {
+            let chunk = match tokio::time::timeout(KEEP_ALIVE, events.recv()).await {
+                Err(_) => web::Bytes::from_static(b": keep-alive\n\n"),
+                Ok(Ok(event)) if raffle_id.is_none_or(|id| id == event.raffle_id) => {
+                    sse_message(&event)
+                }
+                Ok(Ok(_)) => continue,
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    debug!("Event stream skipped {} events", skipped);
+                    continue;
+                }
+                Ok(Err(RecvError::Closed)) => return None,
+            };
+            return Some((Ok::<_, actix_web::Error>(chunk), events));
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
+
+fn sse_message(event: &Event) -> web::Bytes {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    web::Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id.to_hex(),
+        event.kind.as_str(),
+        data
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use std::pin::Pin;
+
+    use actix_web::body::MessageBody;
+    use actix_web::dev::Service;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{App, HttpMessage};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn stream_pushes_events_of_the_raffle() {
+        let event_bus = EventBus::default();
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(ApiKey {
+                        name: "overlay".to_string(),
+                        scopes: vec![Scope::Read],
+                    });
+                    srv.call(req)
+                })
+                .app_data(web::Data::new(event_bus.clone()))
+                .service(stream_events),
+        )
+        .await;
+        let raffle_id = ObjectId::new();
+        let req = TestRequest::get()
+            .uri(&format!("/events?raffle_id={}", raffle_id.to_hex()))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/event-stream");
+
+        event_bus.publish(EventKind::TicketCreated, ObjectId::new(), &"other raffle");
+        event_bus.publish(EventKind::RaffleDrawn, raffle_id, &"this raffle");
+        let mut body = resp.into_body();
+        let chunk = poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await.unwrap().unwrap();
+        let message = String::from_utf8(chunk.to_vec()).unwrap();
+        let lines: Vec<&str> = message.lines().collect();
+        assert!(lines[0].starts_with("id: "));
+        assert_eq!(lines[1], "event: raffle.drawn");
+        let event: Event = serde_json::from_str(lines[2].strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!((event.raffle_id, event.data), (raffle_id, serde_json::json!("this raffle")));
+    }
+}
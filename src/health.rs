@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use actix_web::{get, web, HttpResponse};
+use log::warn;
+use serde_json::json;
+
+use crate::repository::RaffleRepository;
+use crate::settings::Settings;
+use crate::solscan_api;
+
+/// Time a dependency gets to answer a readiness check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process serves requests.
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the database answers, and Solscan too with `health.check_solscan`.
+#[get("/readyz")]
+pub async fn readyz(
+    db_interface: web::Data<dyn RaffleRepository>,
+    settings: web::Data<Settings>,
+) -> HttpResponse {
+    let mut checks = BTreeMap::new();
+    let database = tokio::time::timeout(CHECK_TIMEOUT, db_interface.ping()).await;
+    checks.insert("database", outcome("database", database.map(|result| result.map_err(|err| err.to_string()))));
+    if settings.health.check_solscan {
+        let ping = solscan_api::ping(&settings.solscan.api_url, CHECK_TIMEOUT);
+        let solscan = tokio::time::timeout(CHECK_TIMEOUT, ping).await;
+        checks.insert("solscan", outcome("solscan", solscan.map(|result| result.map_err(|err| err.to_string()))));
+    }
+
+    let ready = checks.values().all(|check| *check == "ok");
+    let mut response = if ready {
+        HttpResponse::Ok()
+    } else {
+        warn!("Not ready: {:?}", checks);
+        HttpResponse::ServiceUnavailable()
+    };
+    response.json(json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "checks": checks,
+    }))
+}
+
+/// The probe is unauthenticated, so error details (hosts, drivers) are only logged.
+fn outcome(check: &str, result: Result<Result<(), String>, tokio::time::error::Elapsed>) -> &'static str {
+    match result {
+        Ok(Ok(())) => "ok",
+        Ok(Err(err)) => {
+            warn!("Readiness check {} failed: {}", check, err);
+            "error"
+        }
+        Err(_) => "timed out",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::db_sql::DatabaseSql;
+
+    #[actix_web::test]
+    async fn probes_report_the_database() {
+        let db = DatabaseSql::connect("sqlite::memory:").await.unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::from(Arc::new(db) as Arc<dyn RaffleRepository>))
+                .app_data(web::Data::new(Settings::default()))
+                .service(healthz)
+                .service(readyz),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::get().uri("/healthz").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let res = call_service(&app, TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let body: Value = read_body_json(res).await;
+        assert_eq!(body, json!({ "status": "ok", "checks": { "database": "ok" } }));
+
+        assert_eq!(outcome("database", Ok(Err("connection refused by db:5432".to_string()))), "error");
+    }
+}
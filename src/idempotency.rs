@@ -0,0 +1,179 @@
+use std::future::Future;
+
+use actix_web::body::to_bytes;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::auth::ApiKey;
+use crate::error::ApiError;
+use crate::repository::{IdempotencyRecord, RaffleRepository, StoredResponse};
+
+pub const HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 255;
+/// Seconds a claim without a response blocks retries, so a crashed request doesn't lock the key.
+const LEASE_SECS: i64 = 60;
+
+/// Runs `handler` once per `Idempotency-Key` of `api_key` and replays its response for retries.
+///
+/// Without the header `handler` simply runs. A key reused for a different request fails
+/// with `idempotency_key_reused`, a retry while the first request still runs with
+/// `request_in_progress`. Server errors are not stored, so the request can be retried.
+/// Keys older than `ttl_secs` are forgotten, claims without a response after `LEASE_SECS`.
+pub async fn run<F, Fut>(
+    db_interface: &dyn RaffleRepository,
+    ttl_secs: i64,
+    api_key: &ApiKey,
+    req: &HttpRequest,
+    body: &[u8],
+    handler: F,
+) -> Result<HttpResponse, ApiError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<HttpResponse, ApiError>>,
+{
+    let Some(key) = req.headers().get(HEADER) else {
+        return handler().await;
+    };
+    let key = match key.to_str() {
+        // Scoped to the API key, clients can't see or block each other's requests
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => format!("{}:{}", api_key.name, key),
+        _ => {
+            return Err(ApiError::InvalidRequest {
+                message: format!("{HEADER} must be 1 to {MAX_KEY_LENGTH} visible characters"),
+            })
+        }
+    };
+
+    let record = IdempotencyRecord {
+        key,
+        request_hash: request_hash(req, body),
+        response: None,
+        date_created: chrono::Utc::now().timestamp(),
+    };
+    if let Some(existing) = claim(db_interface, &record, ttl_secs).await? {
+        if existing.request_hash != record.request_hash {
+            return Err(ApiError::IdempotencyKeyReused);
+        }
+        let response = existing.response.ok_or(ApiError::RequestInProgress)?;
+        info!("Replaying response for {} {}", HEADER, record.key);
+        return Ok(replay(&response));
+    }
+
+    let response = handler().await.unwrap_or_else(|err| err.error_response());
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = match to_bytes(response.into_body()).await {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(_) => String::new(),
+    };
+    let stored = StoredResponse {
+        status: status.as_u16(),
+        content_type,
+        body,
+    };
+
+    let saved = if status.is_server_error() {
+        db_interface.release_idempotency_key(&record).await
+    } else {
+        db_interface.complete_idempotency_key(&record, &stored).await
+    };
+    if let Err(err) = saved {
+        warn!("Could not store response for {} {}: {}", HEADER, record.key, err);
+    }
+    Ok(replay(&stored))
+}
+
+/// Claims the key, replacing a record older than `ttl_secs` or an unfinished one older than `LEASE_SECS`.
+async fn claim(
+    db_interface: &dyn RaffleRepository,
+    record: &IdempotencyRecord,
+    ttl_secs: i64,
+) -> Result<Option<IdempotencyRecord>, ApiError> {
+    let expired = |existing: &IdempotencyRecord| {
+        let lifetime = if existing.response.is_some() { ttl_secs } else { LEASE_SECS.min(ttl_secs) };
+        existing.date_created < record.date_created - lifetime
+    };
+    match db_interface.claim_idempotency_key(record).await? {
+        // Only one of several retries replaces the expired record, the others see its claim
+        Some(existing) if expired(&existing) => {
+            if db_interface.replace_idempotency_key(&existing, record).await? {
+                return Ok(None);
+            }
+            Ok(db_interface.claim_idempotency_key(record).await?)
+        }
+        existing => Ok(existing),
+    }
+}
+
+fn request_hash(req: &HttpRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b" ");
+    hasher.update(req.path());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn replay(response: &StoredResponse) -> HttpResponse {
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::OK);
+    let mut builder = HttpResponse::build(status);
+    if let Some(content_type) = &response.content_type {
+        builder.insert_header((CONTENT_TYPE, content_type.as_str()));
+    }
+    builder.body(response.body.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sql::DatabaseSql;
+
+    fn record(request_hash: &str, date_created: i64) -> IdempotencyRecord {
+        IdempotencyRecord {
+            key: "test:retry".to_string(),
+            request_hash: request_hash.to_string(),
+            response: None,
+            date_created,
+        }
+    }
+
+    #[actix_web::test]
+    async fn expired_claims_are_taken_over_once() {
+        let db = DatabaseSql::connect("sqlite::memory:").await.unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let stale = record("hash", now - LEASE_SECS - 1);
+        assert!(db.claim_idempotency_key(&stale).await.unwrap().is_none());
+
+        // Two retries both read the stale claim, only the first takes it over
+        let (first, second) = (record("hash", now), record("hash", now + 1));
+        assert!(claim(&db, &first, 3600).await.unwrap().is_none());
+        assert!(!db.replace_idempotency_key(&stale, &second).await.unwrap());
+
+        // The slow original request neither completes nor releases the new claim
+        let response = StoredResponse {
+            status: 200,
+            content_type: None,
+            body: "stale".to_string(),
+        };
+        db.complete_idempotency_key(&stale, &response).await.unwrap();
+        db.release_idempotency_key(&stale).await.unwrap();
+        let current = claim(&db, &second, 3600).await.unwrap().unwrap();
+        assert_eq!((current.date_created, current.response.is_none()), (now, true));
+
+        let response = StoredResponse {
+            body: "first".to_string(),
+            ..response
+        };
+        db.complete_idempotency_key(&first, &response).await.unwrap();
+        let current = claim(&db, &second, 3600).await.unwrap().unwrap();
+        assert_eq!(current.response.unwrap().body, "first");
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap};
+
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use log::debug;
+use serde::Deserialize;
+use snafu::prelude::*;
+
+use crate::auth::{ApiKey, Scope};
+use crate::settings::JwtSettings;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("{key} must be set {reason}"))]
+    MissingSetting { key: &'static str, reason: &'static str },
+    #[snafu(display("Unknown algorithm '{algorithm}', expected HS256, RS256 or EdDSA"))]
+    UnknownAlgorithm { algorithm: String },
+    #[snafu(display("jwt.public_key is not a valid {algorithm} PEM key: {source}"))]
+    InvalidKey {
+        algorithm: &'static str,
+        source: jsonwebtoken::errors::Error,
+    },
+}
+
+/// Accepted JWTs: signature algorithm and key, expected audience and issuer, and the
+/// scopes granted to each role.
+pub struct JwtConfig {
+    key: DecodingKey,
+    validation: Validation,
+    roles: HashMap<String, Vec<Scope>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    /// Space separated scopes, as in OAuth 2.0 access tokens.
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+impl JwtConfig {
+    /// `audience` is required, tokens must carry `sub`, `exp` and `aud`; `nbf` is checked when present.
+    pub fn new(
+        algorithm: Algorithm,
+        key: DecodingKey,
+        audience: &str,
+        issuer: Option<&str>,
+        roles: HashMap<String, Vec<Scope>>,
+    ) -> Self {
+        let mut validation = Validation::new(algorithm);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+        validation.validate_nbf = true;
+        if let Some(issuer) = issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        Self {
+            key,
+            validation,
+            roles,
+        }
+    }
+
+    /// Builds the configuration of `settings`, `None` unless `jwt.algorithm` is set.
+    /// `jwt.secret` is the HS256 key, `jwt.public_key` the PEM key of RS256 and EdDSA.
+    pub fn from_settings(settings: &JwtSettings) -> Result<Option<Self>, Error> {
+        let Some(algorithm) = settings.algorithm.as_deref() else {
+            return Ok(None);
+        };
+        let public_key = || {
+            settings.public_key.as_deref().context(MissingSettingSnafu {
+                key: "jwt.public_key",
+                reason: "for RS256 and EdDSA",
+            })
+        };
+        let (algorithm, key) = match algorithm {
+            "HS256" => {
+                let secret = settings
+                    .secret
+                    .as_deref()
+                    .context(MissingSettingSnafu { key: "jwt.secret", reason: "for HS256" })?;
+                (Algorithm::HS256, DecodingKey::from_secret(secret.as_bytes()))
+            }
+            "RS256" => (
+                Algorithm::RS256,
+                DecodingKey::from_rsa_pem(public_key()?.as_bytes())
+                    .context(InvalidKeySnafu { algorithm: "RSA" })?,
+            ),
+            "EdDSA" => (
+                Algorithm::EdDSA,
+                DecodingKey::from_ed_pem(public_key()?.as_bytes())
+                    .context(InvalidKeySnafu { algorithm: "Ed25519" })?,
+            ),
+            _ => {
+                return UnknownAlgorithmSnafu {
+                    algorithm: algorithm.to_string(),
+                }
+                .fail()
+            }
+        };
+        let audience = settings.audience.as_deref().context(MissingSettingSnafu {
+            key: "jwt.audience",
+            reason: "with jwt.algorithm",
+        })?;
+        Ok(Some(Self::new(
+            algorithm,
+            key,
+            audience,
+            settings.issuer.as_deref(),
+            roles(&settings.roles),
+        )))
+    }
+
+    /// Returns the key for a valid token: named `jwt:<sub>`, with the scopes of its `scope`
+    /// claim and of its `roles`. Unknown scopes and roles are ignored.
+    pub fn verify(&self, token: &str) -> Option<ApiKey> {
+        let claims = match decode::<Claims>(token, &self.key, &self.validation) {
+            Ok(data) => data.claims,
+            Err(err) => {
+                debug!("Rejected JWT: {}", err);
+                return None;
+            }
+        };
+        let mut scopes: Vec<Scope> = claims
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|scope| scope.parse().ok())
+            .collect();
+        for role in &claims.roles {
+            scopes.extend(self.roles.get(role).into_iter().flatten());
+        }
+        scopes.sort_by_key(|scope| Scope::ALL.iter().position(|s| s == scope));
+        scopes.dedup();
+        Some(ApiKey {
+            name: format!("jwt:{}", claims.sub),
+            scopes,
+        })
+    }
+}
+
+/// Whether `token` has the three dot separated parts of a JWT.
+pub fn looks_like_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+fn roles(roles: &BTreeMap<String, Vec<Scope>>) -> HashMap<String, Vec<Scope>> {
+    roles
+        .iter()
+        .map(|(role, scopes)| (role.to_lowercase(), scopes.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn config() -> JwtConfig {
+        let roles = HashMap::from([(
+            "moderator".to_string(),
+            vec![Scope::Read, Scope::RaffleAdmin],
+        )]);
+        JwtConfig::new(
+            Algorithm::HS256,
+            DecodingKey::from_secret(SECRET),
+            "raffle-api",
+            Some("issuer"),
+            roles,
+        )
+    }
+
+    fn token(claims: serde_json::Value) -> String {
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[test]
+    fn claims_are_validated_and_mapped_to_scopes() {
+        let now = chrono::Utc::now().timestamp();
+        let valid = json!({
+            "sub": "alice", "aud": "raffle-api", "iss": "issuer", "exp": now + 600,
+            "scope": "ticket:submit unknown", "roles": ["moderator", "guest"],
+        });
+        let key = config().verify(&token(valid.clone())).unwrap();
+        assert_eq!(key.name, "jwt:alice");
+        assert_eq!(key.scopes, [Scope::Read, Scope::TicketSubmit, Scope::RaffleAdmin]);
+
+        let with = |field: &str, value: serde_json::Value| {
+            let mut claims = valid.clone();
+            claims[field] = value;
+            token(claims)
+        };
+        assert!(config().verify(&with("exp", json!(now - 600))).is_none());
+        assert!(config().verify(&with("nbf", json!(now + 600))).is_none());
+        assert!(config().verify(&with("aud", json!("other"))).is_none());
+        assert!(config().verify(&with("iss", json!("other"))).is_none());
+
+        let forged = encode(&Header::new(Algorithm::HS256), &valid, &EncodingKey::from_secret(b"other")).unwrap();
+        assert!(config().verify(&forged).is_none());
+        assert!(looks_like_jwt(&forged));
+        assert!(!looks_like_jwt("rk_0123"));
+    }
+}
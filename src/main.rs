@@ -1,29 +1,50 @@
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
-use actix_web::{App, Error, HttpServer, web};
+use std::sync::Arc;
+use actix_web::{App, Error, HttpMessage, HttpServer, web};
 use actix_web::dev::ServiceRequest;
 use actix_web_httpauth::extractors::AuthenticationError;
 use actix_web_httpauth::extractors::bearer::{BearerAuth, Config};
 use actix_web_httpauth::middleware::HttpAuthentication;
 use log::*;
-use mongodb::{Client};
 use mongodb::bson::oid::ObjectId;
 use rustls::{Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use api::*;
-use db::DatabaseRaffle;
+use repository::RaffleRepository;
+use settings::{ServerSettings, Settings};
+use raffle_model as model;
 use model::*;
 
 
 
 mod api;
-mod config_loader;
+mod api_keys;
+mod audit;
+mod auth;
 mod db;
-mod model;
+mod db_metrics;
+mod db_sql;
+mod discord;
+mod discord_interactions;
+mod error;
+mod events;
+mod health;
+mod idempotency;
+mod jwt;
+mod merge_patch;
+mod metrics;
+#[allow(dead_code)]
 mod mongo_index;
+mod openapi;
+mod rate_limit;
+mod repository;
+mod settings;
 mod solscan_api;
+mod stats;
 mod validator;
+mod webhooks;
 
 //use solana_sdk::*;
 
@@ -33,38 +54,106 @@ async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
     info!("Starting...");
 
-    let server_address = format!("{}:{}", env::var("SERVER_IP").unwrap(), env::var("SERVER_PORT").unwrap());
+    let args: Vec<String> = env::args().skip(1).collect();
+    let vars = env::vars().collect();
+    let settings = Settings::load(&args, &vars).unwrap_or_else(|err| exit_with(err));
+    let server_address = format!("{}:{}", settings.server.ip, settings.server.port);
+    if settings.jwt.algorithm.is_some() {
+        info!("Accepting JWT bearer tokens");
+    }
 
     //Server Setup
-    let m_uri = env::var("MONGODB_URI").unwrap();
-    let client = Client::with_uri_str(m_uri).await.expect("failed to connect");
-    let db_interface = DatabaseRaffle::new();
-    let config = load_certificate();
+    let config = load_certificate(&settings.server).unwrap_or_else(|err| exit_with(err));
+    let db_interface = repository::connect(&settings.database)
+        .await
+        .unwrap_or_else(|err| exit_with(format!("Could not connect to the database: {}", err)));
+    let db_interface: Arc<dyn RaffleRepository> =
+        Arc::new(db_metrics::TimedRepository::new(db_interface));
     info!(
         "Server available at: https:://{} ", server_address
     );
 
+    let rate_limiter = Arc::new(rate_limit::RateLimiter::from_settings(&settings.rate_limit));
+    let event_bus = events::EventBus::default();
+    let retry_policy = webhooks::RetryPolicy::from_settings(&settings.webhooks);
+    webhooks::spawn_dispatcher(db_interface.clone(), &event_bus, retry_policy);
+    if let Some(announcer) = discord::Announcer::from_settings(&settings.discord) {
+        announcer.spawn(db_interface.clone(), &event_bus);
+    }
+    let interactions =
+        discord_interactions::Interactions::from_settings(&settings.discord).map(web::Data::new);
+    let settings = web::Data::new(settings);
+    if interactions.is_some() {
+        info!("Answering Discord interactions");
+    }
+
     HttpServer::new(move || {
         let middleware = HttpAuthentication::bearer(token_validator);
         App::new()
-            .wrap(middleware)
-            .app_data(web::Data::new(client.clone()))
-            .app_data(web::Data::new(db_interface.clone()))
-            .app_data(web::Data::new(config_loader::load_config_file().clone()))
+            .app_data(web::Data::from(db_interface.clone()))
+            .app_data(web::Data::new(event_bus.clone()))
+            .app_data(web::JsonConfig::default().error_handler(error::json_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(error::query_error_handler))
+            .app_data(settings.clone())
+            .wrap(metrics::RequestMetrics)
+            // Probes, readable without a token
+            .service(health::healthz)
+            .service(health::readyz)
+            .service(
+                web::resource("/metrics")
+                    .wrap(HttpAuthentication::bearer(token_validator))
+                    .route(web::get().to(metrics::export)),
+            )
             .service(
                 web::scope("/api/v1")
-                    // API-POST
-                    .service(add_raffle)
-                    .service(add_ticket)
-                    // API-GET
-                    .service(get_raffle)
-                    .service(get_ticket)
-                    // API-DELETE
-                    .service(remove_raffle)
-                    .service(remove_ticket)
-                    // API-UPDATE
-                    .service(update_raffle)
-                    .service(update_ticket),
+                    // API-DOCS, readable without a token
+                    .service(openapi::openapi_json)
+                    .service(openapi::docs)
+                    // Signed by Discord instead of a bearer token
+                    .configure(|cfg| {
+                        if let Some(interactions) = &interactions {
+                            cfg.app_data(interactions.clone())
+                                .app_data(web::Data::new(rate_limiter.clone()))
+                                .service(discord_interactions::interact);
+                        }
+                    })
+                    .service(
+                        web::scope("")
+                            // Registered first, so it runs after the bearer authentication
+                            .wrap(rate_limit::RateLimit::new(rate_limiter.clone()))
+                            .wrap(middleware)
+                            // API-POST
+                            .service(add_raffle)
+                            .service(add_ticket)
+                            // API-GET
+                            .service(get_raffle)
+                            .service(get_ticket)
+                            .service(get_raffle_tickets)
+                            .service(get_raffle_stats)
+                            .service(get_raffle_odds)
+                            .service(get_user_tickets)
+                            .service(list_raffles)
+                            .service(list_tickets)
+                            .service(events::stream_events)
+                            // API-DELETE
+                            .service(remove_raffle)
+                            .service(remove_ticket)
+                            // API-UPDATE
+                            .service(update_raffle)
+                            .service(update_ticket)
+                            // API-KEYS
+                            .service(api_keys::create_key)
+                            .service(api_keys::list_keys)
+                            .service(api_keys::rotate_key)
+                            .service(api_keys::revoke_key)
+                            // API-AUDIT
+                            .service(audit::list_audit)
+                            // API-WEBHOOKS
+                            .service(webhooks::create_webhook)
+                            .service(webhooks::list_webhooks)
+                            .service(webhooks::remove_webhook)
+                            .service(webhooks::list_deliveries),
+                    ),
             )
     })
         /*.bind("localhost:8080")?*/
@@ -81,17 +170,21 @@ async fn token_validator(
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, Error> {
 
+    let db_interface = req
+        .app_data::<web::Data<dyn RaffleRepository>>()
+        .expect("repository is registered")
+        .clone();
+    let settings = req
+        .app_data::<web::Data<Settings>>()
+        .expect("settings are registered")
+        .clone();
+    let key = auth::authenticate(db_interface.as_ref(), &settings, credentials.token())
+        .await
+        .map_err(error::ApiError::from)?;
 
-    let tokens = vec![env::var("API_BEARER_TOKEN").unwrap()];
-
-    let mut valid = false;
-    for token in tokens {
-        if token == credentials.token() {
-            valid = true;
-        }
-    }
-
-    if valid {
+    if let Some(key) = key {
+        debug!("Authenticated as '{}'", key.name);
+        req.extensions_mut().insert(key);
         Ok(req)
     } else {
         let config = req
@@ -103,28 +196,40 @@ async fn token_validator(
     }
 }
 
-fn load_certificate() -> ServerConfig {
+/// Prints a startup error and exits with status 2.
+fn exit_with(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(2);
+}
+
+fn load_certificate(settings: &ServerSettings) -> Result<ServerConfig, String> {
     //Cert Setup
     // load ssl keys
     let config = ServerConfig::builder()
         .with_safe_defaults()
         .with_no_client_auth();
-    let cert_file = &mut BufReader::new(File::open("cert/cert.pem").unwrap());
-    let key_file = &mut BufReader::new(File::open("cert/key.pem").unwrap());
-    let cert_chain = certs(cert_file)
-        .unwrap()
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| format!("Could not read {}: {}", path, err))
+    };
+    let cert_chain: Vec<Certificate> = certs(&mut open(&settings.cert_file)?)
+        .map_err(|err| format!("{} is not a PEM certificate: {}", settings.cert_file, err))?
         .into_iter()
         .map(Certificate)
         .collect();
-    let mut keys: Vec<PrivateKey> = pkcs8_private_keys(key_file)
-        .unwrap()
+    if cert_chain.is_empty() {
+        return Err(format!("{} contains no certificate", settings.cert_file));
+    }
+    let mut keys: Vec<PrivateKey> = pkcs8_private_keys(&mut open(&settings.key_file)?)
+        .map_err(|err| format!("{} is not a PEM key: {}", settings.key_file, err))?
         .into_iter()
         .map(PrivateKey)
         .collect();
     if keys.is_empty() {
-        eprintln!("Could not locate PKCS 8 private keys.");
-        std::process::exit(1);
+        return Err(format!("Could not locate PKCS 8 private keys in {}", settings.key_file));
     }
-    let config = config.with_single_cert(cert_chain, keys.remove(0)).unwrap();
     config
+        .with_single_cert(cert_chain, keys.remove(0))
+        .map_err(|err| format!("Invalid certificate or key: {}", err))
 }
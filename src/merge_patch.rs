@@ -0,0 +1,109 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::ApiError;
+use crate::model::Violation;
+
+/// Applies `patch` to `target` as described by RFC 7396.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Returns `current` with `patch` merged in.
+/// Only `fields` may be patched, those in `immutable` only repeated with their current value.
+pub fn apply<T>(current: &T, patch: &Value, fields: &[&str], immutable: &[&str]) -> Result<T, ApiError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let Value::Object(patched) = patch else {
+        return Err(ApiError::InvalidRequest {
+            message: "Merge patch must be a JSON object".to_string(),
+        });
+    };
+    let mut value = serde_json::to_value(current).map_err(invalid_request)?;
+
+    // Serde would drop them silently, typos and server-managed fields included
+    let violations: Vec<Violation> = patched
+        .keys()
+        .filter(|field| !fields.contains(&field.as_str()))
+        .map(|field| Violation {
+            field: field.clone(),
+            message: "is not a known field".to_string(),
+        })
+        .collect();
+    if !violations.is_empty() {
+        return Err(ApiError::UnknownField { violations });
+    }
+
+    let violations: Vec<Violation> = immutable
+        .iter()
+        .filter(|field| patched.get(**field).is_some_and(|new| value.get(**field) != Some(new)))
+        .map(|field| Violation {
+            field: field.to_string(),
+            message: "is immutable".to_string(),
+        })
+        .collect();
+    if !violations.is_empty() {
+        return Err(ApiError::ImmutableField { violations });
+    }
+
+    merge(&mut value, patch);
+    serde_json::from_value(value).map_err(invalid_request)
+}
+
+fn invalid_request(err: serde_json::Error) -> ApiError {
+    ApiError::InvalidRequest {
+        message: err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn merges_rfc_7396_example() {
+        let mut target = json!({
+            "title": "Goodbye!",
+            "author": {"givenName": "John", "familyName": "Doe"},
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged"
+        });
+        merge(
+            &mut target,
+            &json!({
+                "title": "Hello!",
+                "phoneNumber": "+01-123-456-7890",
+                "author": {"familyName": null},
+                "tags": ["example"]
+            }),
+        );
+        assert_eq!(
+            target,
+            json!({
+                "title": "Hello!",
+                "author": {"givenName": "John"},
+                "tags": ["example"],
+                "content": "This will be unchanged",
+                "phoneNumber": "+01-123-456-7890"
+            })
+        );
+    }
+}
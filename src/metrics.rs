@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::{ready, Ready};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpResponse};
+use futures::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+
+use crate::auth::{ApiKey, Scope};
+use crate::error::ApiError;
+use crate::repository::RaffleRepository;
+
+lazy_static! {
+    /// Metrics of this process, exported by `GET /metrics`.
+    pub static ref METRICS: Registry = Registry::default();
+}
+
+/// Upper bounds in seconds of the latency histogram buckets.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub const HTTP_REQUESTS: &str = "raffle_http_requests_total";
+pub const HTTP_DURATION: &str = "raffle_http_request_duration_seconds";
+pub const TICKET_VALIDATIONS: &str = "raffle_ticket_validations_total";
+pub const SOLSCAN_REQUESTS: &str = "raffle_solscan_requests_total";
+pub const SOLSCAN_DURATION: &str = "raffle_solscan_request_duration_seconds";
+pub const DB_ERRORS: &str = "raffle_db_operation_errors_total";
+pub const DB_DURATION: &str = "raffle_db_operation_duration_seconds";
+pub const TICKETS_SOLD: &str = "raffle_tickets_sold";
+pub const TICKETS_TOTAL: &str = "raffle_tickets_total";
+
+/// Name, type and help of every exported metric, in export order.
+const FAMILIES: [(&str, &str, &str); 9] = [
+    (HTTP_REQUESTS, "counter", "Handled HTTP requests by route and status."),
+    (HTTP_DURATION, "histogram", "Time to answer HTTP requests by route."),
+    (TICKET_VALIDATIONS, "counter", "Checked tickets by outcome, `valid` or the error code."),
+    (SOLSCAN_REQUESTS, "counter", "Solscan transaction lookups by outcome, `ok` or `error`."),
+    (SOLSCAN_DURATION, "histogram", "Time of Solscan transaction lookups."),
+    (DB_ERRORS, "counter", "Failed storage operations by operation."),
+    (DB_DURATION, "histogram", "Time of storage operations by operation."),
+    (TICKETS_SOLD, "gauge", "Tickets sold in running raffles."),
+    (TICKETS_TOTAL, "gauge", "Tickets offered by running raffles."),
+];
+
+/// Label names and values of one series.
+pub type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of `BUCKETS`, not cumulative; larger ones only count in `count`.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Counters and histograms kept in memory, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Registry {
+    counters: Mutex<BTreeMap<(&'static str, Labels), u64>>,
+    histograms: Mutex<BTreeMap<(&'static str, Labels), Histogram>>,
+}
+
+impl Registry {
+    pub fn inc(&self, name: &'static str, labels: Labels) {
+        *self.counters.lock().unwrap().entry((name, labels)).or_default() += 1;
+    }
+
+    pub fn observe(&self, name: &'static str, labels: Labels, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry((name, labels)).or_default();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Renders every series and the given gauges.
+    pub fn render(&self, gauges: &[(&'static str, Labels, f64)]) -> String {
+        let counters = self.counters.lock().unwrap();
+        let histograms = self.histograms.lock().unwrap();
+        let mut text = String::new();
+        for (family, kind, help) in FAMILIES {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", family, help, family, kind);
+            for ((name, labels), value) in counters.iter() {
+                if *name == family {
+                    sample(&mut text, name, labels, *value as f64);
+                }
+            }
+            for ((name, labels), histogram) in histograms.iter() {
+                if *name != family {
+                    continue;
+                }
+                let bucket_name = format!("{}_bucket", name);
+                let mut cumulative = 0;
+                for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                    cumulative += count;
+                    let labels = with_label(labels, "le", bound.to_string());
+                    sample(&mut text, &bucket_name, &labels, cumulative as f64);
+                }
+                let labels_inf = with_label(labels, "le", "+Inf".to_string());
+                sample(&mut text, &bucket_name, &labels_inf, histogram.count as f64);
+                sample(&mut text, &format!("{}_sum", name), labels, histogram.sum);
+                sample(&mut text, &format!("{}_count", name), labels, histogram.count as f64);
+            }
+            for (name, labels, value) in gauges {
+                if *name == family {
+                    sample(&mut text, name, labels, *value);
+                }
+            }
+        }
+        text
+    }
+}
+
+fn with_label(labels: &Labels, name: &'static str, value: String) -> Labels {
+    let mut labels = labels.clone();
+    labels.push((name, value));
+    labels
+}
+
+fn sample(text: &mut String, name: &str, labels: &Labels, value: f64) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    if labels.is_empty() {
+        let _ = writeln!(text, "{} {}", name, value);
+    } else {
+        let _ = writeln!(text, "{}{{{}}} {}", name, labels.join(","), value);
+    }
+}
+
+/// Middleware counting and timing every request by method, route pattern and status.
+pub struct RequestMetrics;
+
+impl<S> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        // Read before the call, errors of middlewares like the bearer authentication carry no request
+        let route = req.match_pattern();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let result = response.await;
+            let status = match &result {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            // Unmatched paths are not used as labels, anyone could create any number of them
+            let route = route.unwrap_or_else(|| "unmatched".to_string());
+            let labels = vec![("method", method), ("route", route)];
+            METRICS.observe(HTTP_DURATION, labels.clone(), start.elapsed());
+            METRICS.inc(HTTP_REQUESTS, with_label(&labels, "status", status.as_u16().to_string()));
+            result
+        })
+    }
+}
+
+/// `GET /metrics`, with the ticket gauges of the running raffles read at scrape time.
+pub async fn export(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::Read)?;
+    let mut gauges = Vec::new();
+    for (raffle, sold) in db_interface.get_tickets_sold("running").await? {
+        // Titles are neither unique nor stable, the id keeps one series per raffle
+        let labels = vec![("raffle_id", raffle.id.to_hex())];
+        gauges.push((TICKETS_SOLD, labels.clone(), sold as f64));
+        gauges.push((TICKETS_TOTAL, labels, raffle.ticket_amount as f64));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.render(&gauges)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{App, HttpMessage};
+
+    use super::*;
+
+    #[test]
+    fn series_are_rendered_in_the_text_format() {
+        let registry = Registry::default();
+        registry.inc(SOLSCAN_REQUESTS, vec![("outcome", "ok".to_string())]);
+        registry.inc(SOLSCAN_REQUESTS, vec![("outcome", "ok".to_string())]);
+        registry.observe(SOLSCAN_DURATION, vec![], Duration::from_millis(30));
+        registry.observe(SOLSCAN_DURATION, vec![], Duration::from_secs(60));
+        let gauges = [(TICKETS_SOLD, vec![("raffle_id", "say \"hi\"".to_string())], 3.0)];
+        let text = registry.render(&gauges);
+
+        assert!(text.contains("# TYPE raffle_solscan_requests_total counter\n"));
+        assert!(text.contains("raffle_solscan_requests_total{outcome=\"ok\"} 2\n"));
+        assert!(text.contains("raffle_solscan_request_duration_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(text.contains("raffle_solscan_request_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(text.contains("raffle_solscan_request_duration_seconds_bucket{le=\"10\"} 1\n"));
+        assert!(text.contains("raffle_solscan_request_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("raffle_solscan_request_duration_seconds_count 2\n"));
+        assert!(text.contains("raffle_tickets_sold{raffle_id=\"say \\\"hi\\\"\"} 3\n"));
+    }
+
+    #[actix_web::test]
+    async fn requests_are_counted_by_route() {
+        let app = init_service(
+            App::new()
+                .wrap(RequestMetrics)
+                .route("/things/{id}", web::get().to(HttpResponse::Ok))
+                .service(
+                    web::scope("/secret")
+                        .wrap_fn(|_, _| async {
+                            Err::<ServiceResponse, _>(actix_web::error::ErrorUnauthorized("no"))
+                        })
+                        .route("/{id}", web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+        call_service(&app, TestRequest::get().uri("/things/1").to_request()).await;
+        call_service(&app, TestRequest::get().uri("/things/2").to_request()).await;
+        call_service(&app, TestRequest::get().uri("/nothing").to_request()).await;
+        let res = app.call(TestRequest::get().uri("/secret/1").to_request()).await;
+        assert!(res.is_err());
+
+        let text = METRICS.render(&[]);
+        assert!(text.contains(
+            "raffle_http_requests_total{method=\"GET\",route=\"/things/{id}\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "raffle_http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"}"
+        ));
+        assert!(text.contains(
+            "raffle_http_request_duration_seconds_count{method=\"GET\",route=\"/things/{id}\"} 2\n"
+        ));
+        assert!(text.contains(
+            "raffle_http_requests_total{method=\"GET\",route=\"/secret/{id}\",status=\"401\"} 1\n"
+        ));
+
+        let db = crate::db_sql::DatabaseSql::connect("sqlite::memory:").await.unwrap();
+        let mut ids = Vec::new();
+        for status in ["running", "created"] {
+            let mut raffle: crate::Raffle = serde_json::from_value(serde_json::json!({
+                "title": status, "description": "d", "ticket_amount": 10,
+                "ticket_price": 1.0, "ticket_token_name": "USDC"
+            }))
+            .unwrap();
+            db.insert_raffle(&mut raffle).await.unwrap();
+            raffle.status = status.to_string();
+            db.update_raffle(&mut raffle).await.unwrap();
+            for amount in [2, 3] {
+                let mut ticket: crate::Ticket = serde_json::from_value(serde_json::json!({
+                    "raffle_id": raffle.id, "username": "u", "spl_tx_signature": "s", "amount": amount
+                }))
+                .unwrap();
+                db.insert_ticket(&mut ticket).await.unwrap();
+            }
+            ids.push(raffle.id.to_hex());
+        }
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(ApiKey {
+                        name: "prometheus".to_string(),
+                        scopes: vec![Scope::Read],
+                    });
+                    srv.call(req)
+                })
+                .app_data(web::Data::from(
+                    std::sync::Arc::new(db) as std::sync::Arc<dyn RaffleRepository>
+                ))
+                .route("/metrics", web::get().to(export)),
+        )
+        .await;
+        let res = call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(res.status(), 200);
+        let body = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        assert!(body.contains("# TYPE raffle_tickets_sold gauge\n"));
+        let running = format!("raffle_tickets_sold{{raffle_id=\"{}\"}} 5\n", ids[0]);
+        assert!(body.contains(&running), "{}", body);
+        assert!(!body.contains(&ids[1]), "{}", body);
+    }
+}
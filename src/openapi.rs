@@ -0,0 +1,66 @@
+use actix_web::{get, HttpResponse};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{api, api_keys, audit, events, webhooks};
+use crate::model::*;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Raffle API", description = "REST API for hosting raffles paid with SPL tokens."),
+    servers((url = "/api/v1")),
+    paths(
+        api::add_raffle,
+        api::add_ticket,
+        api::get_raffle,
+        api::get_raffle_tickets,
+        api::get_raffle_stats,
+        api::get_raffle_odds,
+        api::get_user_tickets,
+        api::list_raffles,
+        api::list_tickets,
+        api::get_ticket,
+        api::update_raffle,
+        api::update_ticket,
+        api::remove_raffle,
+        api::remove_ticket,
+        api_keys::create_key,
+        api_keys::list_keys,
+        api_keys::rotate_key,
+        api_keys::revoke_key,
+        audit::list_audit,
+        events::stream_events,
+        webhooks::create_webhook,
+        webhooks::list_webhooks,
+        webhooks::remove_webhook,
+        webhooks::list_deliveries,
+    ),
+    components(schemas(ObjectIdSchema, Raffle, EmbedTemplate, ValidationPolicy, Ticket, SortOrder, RaffleStats, Buyer, UserOdds, PrizeOdds, ErrorBody, Violation, ApiKeyInfo, NewApiKey, CreatedApiKey, AuditEntry, AuditChange, Event, EventKind, Webhook, NewWebhook, CreatedWebhook, WebhookDelivery)),
+    modifiers(&BearerAuth),
+    security(("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+#[get("/openapi.json")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[get("/docs")]
+pub async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("docs.html"))
+}
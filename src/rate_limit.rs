@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::http::Method;
+use actix_web::{web, Error, FromRequest, HttpMessage};
+use futures::future::LocalBoxFuture;
+use futures::stream::{self, Stream};
+use log::warn;
+
+use crate::auth::ApiKey;
+use crate::error::ApiError;
+use crate::settings::RateLimitSettings;
+
+/// Buckets are swept once the map grows past this many entries.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Requests allowed per `window`; a bucket refills continuously, so bursts up to
+/// `requests` are allowed after a quiet window.
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+    pub requests: u32,
+    pub window: Duration,
+}
+
+impl Limit {
+    fn refill_per_sec(&self) -> f64 {
+        self.requests as f64 / self.window.as_secs_f64()
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets shared by every worker. A limit of `None` disables that bucket kind.
+pub struct RateLimiter {
+    per_key: Option<Limit>,
+    per_user: Option<Limit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_key: Option<Limit>, per_user: Option<Limit>) -> Self {
+        Self {
+            per_key,
+            per_user,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Requests per key and ticket submissions per username per window. A limit of 0
+    /// disables it.
+    pub fn from_settings(settings: &RateLimitSettings) -> Self {
+        let window = Duration::from_secs(settings.window_secs.max(1));
+        let limit = |requests: u32| (requests > 0).then_some(Limit { requests, window });
+        Self::new(limit(settings.key), limit(settings.user_tickets))
+    }
+
+    /// Takes one token from the bucket `name`, or fails with the seconds until one is available.
+    fn take(&self, name: String, limit: Limit, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= SWEEP_THRESHOLD {
+            // Buckets that refilled completely behave like new ones.
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < limit.window);
+        }
+        let bucket = buckets.entry(name).or_insert(Bucket {
+            tokens: limit.requests as f64,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_sec()).min(limit.requests as f64);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / limit.refill_per_sec()).ceil() as u64)
+        }
+    }
+
+    pub fn check_key(&self, key: &str) -> Result<(), ApiError> {
+        match self.per_key {
+            Some(limit) => self.check(format!("key:{}", key), limit),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_user(&self, username: &str) -> Result<(), ApiError> {
+        match self.per_user {
+            Some(limit) => self.check(format!("user:{}", username), limit),
+            None => Ok(()),
+        }
+    }
+
+    fn check(&self, name: String, limit: Limit) -> Result<(), ApiError> {
+        self.take(name.clone(), limit, Instant::now()).map_err(|retry_after| {
+            warn!("Rate limit exceeded for {}", name);
+            ApiError::RateLimited { retry_after }
+        })
+    }
+}
+
+/// Middleware limiting requests per API key and ticket submissions per username.
+/// Must be wrapped inside the bearer authentication, which provides the [`ApiKey`].
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            let key = req.extensions().get::<ApiKey>().map(|key| key.name.clone());
+            if let Some(Err(err)) = key.map(|key| limiter.check_key(&key)) {
+                return Ok(req.error_response(err));
+            }
+            if is_ticket_submission(&req) {
+                // The body is read here and handed back to the handler unchanged.
+                let (http_req, payload) = req.parts_mut();
+                let body = web::Bytes::from_request(http_req, payload).await?;
+                let username = serde_json::from_slice::<serde_json::Value>(&body)
+                    .ok()
+                    .and_then(|value| value.get("username")?.as_str().map(str::to_string));
+                req.set_payload(bytes_to_payload(body));
+                if let Some(Err(err)) = username.map(|username| limiter.check_user(&username)) {
+                    return Ok(req.error_response(err));
+                }
+            }
+            service.call(req).await
+        })
+    }
+}
+
+fn is_ticket_submission(req: &ServiceRequest) -> bool {
+    req.method() == Method::POST && req.path().trim_end_matches('/').ends_with("/ticket")
+}
+
+fn bytes_to_payload(body: web::Bytes) -> Payload {
+    let stream: Pin<Box<dyn Stream<Item = Result<web::Bytes, PayloadError>>>> =
+        Box::pin(stream::once(ready(Ok(body))));
+    Payload::from(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{App, HttpResponse, ResponseError};
+
+    fn limit(requests: u32) -> Option<Limit> {
+        Some(Limit {
+            requests,
+            window: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn buckets_refill_over_the_window() {
+        let limiter = RateLimiter::new(limit(2), None);
+        let start = Instant::now();
+        let limit = limit(2).unwrap();
+        assert!(limiter.take("a".into(), limit, start).is_ok());
+        assert!(limiter.take("a".into(), limit, start).is_ok());
+        assert_eq!(limiter.take("a".into(), limit, start), Err(30));
+        assert!(limiter.take("b".into(), limit, start).is_ok());
+        assert!(limiter.take("a".into(), limit, start + Duration::from_secs(30)).is_ok());
+        assert!(limiter.take("a".into(), limit, start + Duration::from_secs(30)).is_err());
+    }
+
+    #[actix_web::test]
+    async fn ticket_submissions_are_limited_per_username() {
+        let limiter = Arc::new(RateLimiter::new(limit(10), limit(1)));
+        let app = init_service(
+            App::new().service(
+                web::scope("")
+                    .wrap(RateLimit::new(limiter))
+                    .wrap_fn(|req, srv| {
+                        req.extensions_mut().insert(ApiKey {
+                            name: "bot".to_string(),
+                            scopes: vec![Scope::TicketSubmit],
+                        });
+                        srv.call(req)
+                    })
+                    .route(
+                        "/ticket",
+                        web::post().to(|body: web::Json<serde_json::Value>| async move {
+                            HttpResponse::Ok().json(body.into_inner())
+                        }),
+                    ),
+            ),
+        )
+        .await;
+        let submit = |username: &str| {
+            TestRequest::post()
+                .uri("/ticket")
+                .set_json(serde_json::json!({ "username": username }))
+                .to_request()
+        };
+
+        let res = call_service(&app, submit("alice")).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["username"], "alice");
+
+        let res = call_service(&app, submit("alice")).await;
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "60");
+        assert_eq!(call_service(&app, submit("bob")).await.status(), 200);
+
+        let err = ApiError::RateLimited { retry_after: 1 };
+        assert_eq!(err.status_code(), 429);
+    }
+}
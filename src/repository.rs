@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::info;
+use mongodb::bson::oid::ObjectId;
+use mongodb::Client;
+use snafu::prelude::*;
+
+use crate::db::DatabaseRaffle;
+use crate::db_sql::DatabaseSql;
+use crate::settings::DatabaseSettings;
+use crate::model::{ApiKeyInfo, AuditEntry, Page, Raffle, SortOrder, Ticket, Webhook, WebhookDelivery};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("MongoDB error: {source}"))]
+    Mongo { source: mongodb::error::Error },
+    #[snafu(display("SQL error: {source}"))]
+    Sql { source: sqlx::Error },
+    #[snafu(display("SQL migration failed: {source}"))]
+    Migrate { source: sqlx::migrate::MigrateError },
+    #[snafu(display("Could not convert to BSON: {source}"))]
+    Bson { source: mongodb::bson::ser::Error },
+    #[snafu(display("Stored JSON is invalid: {source}"))]
+    Json { source: serde_json::Error },
+    #[snafu(display("Stored id '{value}' is not a valid ObjectId"))]
+    InvalidStoredId { value: String },
+    #[snafu(display("Stored value '{value}' is invalid"))]
+    InvalidStoredValue { value: String },
+    #[snafu(display("Raffle {id} does not exist"))]
+    RaffleNotFound { id: ObjectId },
+    #[snafu(display("Database backend '{backend}' is unknown or lacks its URL"))]
+    UnknownBackend { backend: String },
+}
+
+pub const DEFAULT_PAGE_LIMIT: u32 = 50;
+pub const MAX_PAGE_LIMIT: u32 = 500;
+
+#[derive(Clone, Debug, Default)]
+pub struct RaffleFilter {
+    pub status: Option<String>,
+    pub token: Option<String>,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TicketFilter {
+    pub raffle_id: Option<ObjectId>,
+    pub username: Option<String>,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub collection: Option<String>,
+    pub document_id: Option<ObjectId>,
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+}
+
+/// Keyset pagination over creation order: documents are sorted by id, and `cursor` is the
+/// id of the last document of the previous page.
+#[derive(Clone, Copy, Debug)]
+pub struct PageRequest {
+    pub limit: u32,
+    pub cursor: Option<ObjectId>,
+    pub sort: SortOrder,
+}
+
+impl PageRequest {
+    pub fn new(limit: Option<u32>, cursor: Option<ObjectId>, sort: SortOrder) -> Self {
+        Self {
+            limit: limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT),
+            cursor,
+            sort,
+        }
+    }
+
+    /// Backends fetch one document more than requested to know whether a next page exists.
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit as i64 + 1
+    }
+
+    /// Builds the page from the result of a query limited to `fetch_limit` documents.
+    pub fn into_page<T>(self, mut items: Vec<T>, id: impl Fn(&T) -> ObjectId) -> Page<T> {
+        let next_cursor = if items.len() > self.limit as usize {
+            items.truncate(self.limit as usize);
+            items.last().map(|item| id(item).to_hex())
+        } else {
+            None
+        };
+        Page { items, next_cursor }
+    }
+}
+
+/// A request seen with an `Idempotency-Key` header.
+#[derive(Clone, Debug)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    /// Hash of method, path and body of the first request with this key.
+    pub request_hash: String,
+    /// The stored response, `None` while the first request is still running.
+    pub response: Option<StoredResponse>,
+    pub date_created: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+/// An API key as stored: only the SHA-256 hash of the token is kept.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredApiKey {
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    pub key_hash: String,
+}
+
+/// A webhook as stored, with the secret its deliveries are signed with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredWebhook {
+    #[serde(flatten)]
+    pub info: Webhook,
+    pub secret: String,
+}
+
+/// Storage operations for raffles and tickets, implemented by every backend.
+#[async_trait]
+pub trait RaffleRepository: Send + Sync {
+    //region === INSERT ===
+    /// Inserts `raffle` with a new id, the current dates and `version` 0. The status is kept
+    /// when it is `running`, any other becomes `created`.
+    async fn insert_raffle(&self, raffle: &mut Raffle) -> Result<(), Error>;
+
+    /// Inserts `ticket` with the current dates and `version` 0, without checking or counting
+    /// it against the tickets left; sold tickets go through `allocate_ticket`.
+    async fn insert_ticket(&self, ticket: &mut Ticket) -> Result<(), Error>;
+
+    /// Caps `ticket.amount` to the tickets still left in its raffle and inserts it; nothing is
+    /// inserted when that amount is 0. Closes the raffle once it is sold out and returns whether
+    /// this call closed it.
+    async fn allocate_ticket(&self, ticket: &mut Ticket) -> Result<bool, Error>;
+    //endregion
+
+    //region === REMOVE ===
+    async fn remove_raffle(&self, raffle_id: ObjectId) -> Result<u64, Error>;
+
+    async fn remove_ticket(&self, ticket_id: ObjectId) -> Result<u64, Error>;
+    //endregion
+
+    //region === FIND ALL ===
+    async fn get_all_raffles(&self) -> Result<Vec<Raffle>, Error>;
+
+    async fn get_all_tickets(&self) -> Result<Vec<Ticket>, Error>;
+    //endregion
+
+    //region === FIND PAGED ===
+    async fn find_raffles(
+        &self,
+        filter: &RaffleFilter,
+        page: PageRequest,
+    ) -> Result<Page<Raffle>, Error>;
+
+    async fn find_tickets(
+        &self,
+        filter: &TicketFilter,
+        page: PageRequest,
+    ) -> Result<Page<Ticket>, Error>;
+    //endregion
+
+    //region === FIND BY ID ===
+    async fn get_raffle_by_id(&self, id: ObjectId) -> Result<Vec<Raffle>, Error>;
+
+    async fn get_ticket_by_id(&self, id: ObjectId) -> Result<Vec<Ticket>, Error>;
+    //endregion
+
+    //region === FIND SPECIAL ===
+    async fn get_tickets_by_id_raffle(&self, id: ObjectId) -> Result<Vec<Ticket>, Error>;
+
+    async fn get_spl_tx_in_ticket(&self, spl_tx_signature: &str) -> Result<Option<Ticket>, Error>;
+
+    /// The raffles with `status` and the tickets sold in each, without reading every ticket.
+    async fn get_tickets_sold(&self, status: &str) -> Result<Vec<(Raffle, u64)>, Error>;
+    //endregion
+
+    //region === UPDATE ===
+    /// Only matches while the stored `version` equals `raffle.version`, which is then incremented.
+    async fn update_raffle(&self, raffle: &mut Raffle) -> Result<u64, Error>;
+
+    /// Only matches while the stored `version` equals `ticket.version`.
+    async fn update_ticket(&self, ticket: &Ticket) -> Result<u64, Error>;
+    //endregion
+
+    //region === IDEMPOTENCY ===
+    /// Stores `record` unless its key is taken, in which case the existing record is returned.
+    async fn claim_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+    ) -> Result<Option<IdempotencyRecord>, Error>;
+
+    /// Replaces `stale` with `record` unless another request replaced or released it first.
+    async fn replace_idempotency_key(
+        &self,
+        stale: &IdempotencyRecord,
+        record: &IdempotencyRecord,
+    ) -> Result<bool, Error>;
+
+    /// Stores the response of `claim`, unless the key has been claimed anew since.
+    async fn complete_idempotency_key(
+        &self,
+        claim: &IdempotencyRecord,
+        response: &StoredResponse,
+    ) -> Result<(), Error>;
+
+    /// Removes `claim`, unless the key has been claimed anew since.
+    async fn release_idempotency_key(&self, claim: &IdempotencyRecord) -> Result<(), Error>;
+    //endregion
+
+    //region === API KEYS ===
+    async fn insert_api_key(&self, key: &StoredApiKey) -> Result<(), Error>;
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKeyInfo>, Error>;
+
+    async fn get_api_key_by_id(&self, id: ObjectId) -> Result<Option<ApiKeyInfo>, Error>;
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<StoredApiKey>, Error>;
+
+    /// Replaces the token of a key that is not revoked.
+    async fn rotate_api_key(&self, id: ObjectId, key_hash: &str, prefix: &str) -> Result<u64, Error>;
+
+    /// Revokes a key that is not revoked yet.
+    async fn revoke_api_key(&self, id: ObjectId, date_revoked: i64) -> Result<u64, Error>;
+
+    async fn touch_api_key(&self, id: ObjectId, date_last_used: i64) -> Result<(), Error>;
+    //endregion
+
+    //region === AUDIT ===
+    /// The audit log is append-only, entries are never updated or removed.
+    async fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), Error>;
+
+    async fn find_audit_entries(
+        &self,
+        filter: &AuditFilter,
+        page: PageRequest,
+    ) -> Result<Page<AuditEntry>, Error>;
+    //endregion
+
+    //region === WEBHOOKS ===
+    async fn insert_webhook(&self, webhook: &StoredWebhook) -> Result<(), Error>;
+
+    async fn get_webhooks(&self) -> Result<Vec<StoredWebhook>, Error>;
+
+    async fn remove_webhook(&self, id: ObjectId) -> Result<u64, Error>;
+
+    async fn insert_webhook_delivery(&self, delivery: &WebhookDelivery) -> Result<(), Error>;
+
+    async fn find_webhook_deliveries(
+        &self,
+        webhook_id: ObjectId,
+        page: PageRequest,
+    ) -> Result<Page<WebhookDelivery>, Error>;
+    //endregion
+
+    //region === HEALTH ===
+    /// Round trip to the database, used by `GET /readyz`.
+    async fn ping(&self) -> Result<(), Error>;
+    //endregion
+}
+
+/// Returns how many of `requested` tickets fit into `raffle` when `sold` are already taken.
+pub fn tickets_left(raffle: &Raffle, sold: u16, requested: u16) -> u16 {
+    raffle.ticket_amount.saturating_sub(sold).min(requested)
+}
+
+/// Connects to the backend selected by `settings.backend`.
+///
+/// `mongo` uses `settings.mongodb_uri`; `postgres` and `sqlite` use `settings.url` and run
+/// the bundled migrations before returning.
+pub async fn connect(settings: &DatabaseSettings) -> Result<Arc<dyn RaffleRepository>, Error> {
+    info!("Using {} storage backend", settings.backend);
+    match (settings.backend.as_str(), &settings.mongodb_uri, &settings.url) {
+        ("mongo", Some(uri), _) => {
+            let client = Client::with_uri_str(uri).await.context(MongoSnafu)?;
+            Ok(Arc::new(DatabaseRaffle::new(client, settings.clone())))
+        }
+        ("postgres" | "sqlite", _, Some(url)) => Ok(Arc::new(DatabaseSql::connect(url).await?)),
+        (backend, _, _) => UnknownBackendSnafu { backend }.fail(),
+    }
+}
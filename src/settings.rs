@@ -0,0 +1,625 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use config::{Config, File, FileFormat};
+use log::warn;
+use serde::Deserialize;
+use snafu::prelude::*;
+
+use crate::auth::Scope;
+use crate::jwt::JwtConfig;
+use crate::model::{EventKind, ValidationPolicy};
+
+/// Used unless `--config` or `RAFFLE_CONFIG` names another file; it may be missing.
+pub const DEFAULT_FILE: &str = "conf/settings.toml";
+
+/// Environment variables and the settings they override; `true` marks comma separated lists.
+/// `API_KEYS`, `API_KEY_<NAME>[_SCOPES]` and `JWT_ROLE_<ROLE>_SCOPES` are read by `load`.
+const ENV_OVERRIDES: [(&str, &str, bool); 43] = [
+    ("SERVER_IP", "server.ip", false),
+    ("SERVER_PORT", "server.port", false),
+    ("SERVER_CERT_FILE", "server.cert_file", false),
+    ("SERVER_KEY_FILE", "server.key_file", false),
+    ("DB_BACKEND", "database.backend", false),
+    ("MONGODB_URI", "database.mongodb_uri", false),
+    ("DATABASE_URL", "database.url", false),
+    ("DB_NAME", "database.name", false),
+    ("COLL_RAFFLE", "database.collections.raffle", false),
+    ("COLL_TICKET", "database.collections.ticket", false),
+    ("COLL_API_KEY", "database.collections.api_key", false),
+    ("COLL_AUDIT", "database.collections.audit", false),
+    ("COLL_WEBHOOK", "database.collections.webhook", false),
+    ("COLL_WEBHOOK_DELIVERY", "database.collections.webhook_delivery", false),
+    ("COLL_IDEMPOTENCY", "database.collections.idempotency", false),
+    ("CHECK_TOKEN_SYMBOL", "validation.check_token_symbol", false),
+    ("CHECK_TX_STATUS", "validation.check_tx_status", false),
+    ("CHECK_RAFFLE_RUNNING", "validation.check_raffle_running", false),
+    ("CHECK_RAFFLE_TIME", "validation.check_raffle_time", false),
+    ("CHECK_RAFFLE_DESTINATION", "validation.check_raffle_destination", false),
+    ("CHECK_RAFFLE_USED_SIGNATURE", "validation.check_raffle_used_signature", false),
+    ("SOL_WALLET", "validation.sol_wallet", false),
+    ("RAFFLE_TOKENS", "validation.tokens", true),
+    ("SOLSCAN_API_URL", "solscan.api_url", false),
+    ("SOLSCAN_TIMEOUT_SECS", "solscan.timeout_secs", false),
+    ("RATE_LIMIT_KEY", "rate_limit.key", false),
+    ("RATE_LIMIT_USER_TICKETS", "rate_limit.user_tickets", false),
+    ("RATE_LIMIT_WINDOW_SECS", "rate_limit.window_secs", false),
+    ("WEBHOOK_MAX_ATTEMPTS", "webhooks.max_attempts", false),
+    ("WEBHOOK_RETRY_SECS", "webhooks.retry_secs", false),
+    ("WEBHOOK_TIMEOUT_SECS", "webhooks.timeout_secs", false),
+    ("IDEMPOTENCY_TTL_SECS", "idempotency.ttl_secs", false),
+    ("DISCORD_WEBHOOK_URLS", "discord.webhook_urls", true),
+    ("DISCORD_EVENTS", "discord.events", true),
+    ("DISCORD_PUBLIC_KEY", "discord.public_key", false),
+    ("DISCORD_API_URL", "discord.api_url", false),
+    ("READYZ_CHECK_SOLSCAN", "health.check_solscan", false),
+    ("API_BEARER_TOKEN", "auth.bearer_token", false),
+    ("JWT_ALGORITHM", "jwt.algorithm", false),
+    ("JWT_SECRET", "jwt.secret", false),
+    ("JWT_PUBLIC_KEY", "jwt.public_key", false),
+    ("JWT_AUDIENCE", "jwt.audience", false),
+    ("JWT_ISSUER", "jwt.issuer", false),
+];
+
+/// Environment variables that no longer have an effect, and why.
+const REMOVED_ENV: [(&str, &str); 1] = [(
+    "CHECK_RAFFLE_EXISTS",
+    "tickets for unknown raffles are always rejected",
+)];
+
+const USAGE: &str = "Usage: raffle_mongo_api [--config <file>] [--set <key>=<value>]...";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("{message}\n{USAGE}"))]
+    Usage { message: String },
+    #[snafu(display("Configuration file {path} is missing"))]
+    MissingFile { path: String },
+    #[snafu(display("Could not read the configuration: {source}"))]
+    Load { source: config::ConfigError },
+    #[snafu(display("Invalid configuration:\n  {}", problems.join("\n  ")))]
+    Invalid { problems: Vec<String> },
+}
+
+/// Settings of the whole server, layered from defaults, the TOML file, environment
+/// variables and `--set` flags, each overriding the ones before.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub validation: ValidationSettings,
+    pub solscan: SolscanSettings,
+    pub rate_limit: RateLimitSettings,
+    pub webhooks: WebhookSettings,
+    pub idempotency: IdempotencySettings,
+    pub discord: DiscordSettings,
+    pub health: HealthSettings,
+    pub auth: AuthSettings,
+    pub jwt: JwtSettings,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub ip: String,
+    pub port: u16,
+    pub cert_file: String,
+    pub key_file: String,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            ip: "0.0.0.0".to_string(),
+            port: 8080,
+            cert_file: "cert/cert.pem".to_string(),
+            key_file: "cert/key.pem".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    /// `mongo`, `postgres` or `sqlite`.
+    pub backend: String,
+    /// Used by the mongo backend.
+    pub mongodb_uri: Option<String>,
+    /// Used by the postgres and sqlite backends.
+    pub url: Option<String>,
+    /// Mongo database name.
+    pub name: String,
+    pub collections: Collections,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        Self {
+            backend: "mongo".to_string(),
+            mongodb_uri: None,
+            url: None,
+            name: "DB_Raffle".to_string(),
+            collections: Collections::default(),
+        }
+    }
+}
+
+/// Mongo collection names.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Collections {
+    pub raffle: String,
+    pub ticket: String,
+    pub api_key: String,
+    pub audit: String,
+    pub webhook: String,
+    pub webhook_delivery: String,
+    pub idempotency: String,
+}
+
+impl Default for Collections {
+    fn default() -> Self {
+        Self {
+            raffle: "Raffle".to_string(),
+            ticket: "Ticket".to_string(),
+            api_key: "ApiKey".to_string(),
+            audit: "Audit".to_string(),
+            webhook: "Webhook".to_string(),
+            webhook_delivery: "WebhookDelivery".to_string(),
+            idempotency: "Idempotency".to_string(),
+        }
+    }
+}
+
+/// Checks applied to submitted tickets, all off by default; a raffle's `validation` overrides them.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationSettings {
+    pub check_token_symbol: bool,
+    pub check_tx_status: bool,
+    pub check_raffle_running: bool,
+    pub check_raffle_time: bool,
+    pub check_raffle_destination: bool,
+    pub check_raffle_used_signature: bool,
+    /// Wallet ticket payments must be sent to, required by `check_raffle_destination`.
+    pub sol_wallet: Option<String>,
+    /// Tokens raffles may be sold in, USDC and SOL by default.
+    pub tokens: Vec<String>,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            check_token_symbol: false,
+            check_tx_status: false,
+            check_raffle_running: false,
+            check_raffle_time: false,
+            check_raffle_destination: false,
+            check_raffle_used_signature: false,
+            sol_wallet: None,
+            tokens: vec!["USDC".to_string(), "SOL".to_string()],
+        }
+    }
+}
+
+impl ValidationSettings {
+    /// The checks of a raffle with `policy`, falling back to these settings.
+    pub fn for_raffle(&self, policy: &ValidationPolicy) -> ValidationSettings {
+        ValidationSettings {
+            check_token_symbol: policy.check_token_symbol.unwrap_or(self.check_token_symbol),
+            check_tx_status: policy.check_tx_status.unwrap_or(self.check_tx_status),
+            check_raffle_running: policy.check_raffle_running.unwrap_or(self.check_raffle_running),
+            check_raffle_time: policy.check_raffle_time.unwrap_or(self.check_raffle_time),
+            check_raffle_destination: policy
+                .check_raffle_destination
+                .unwrap_or(self.check_raffle_destination),
+            check_raffle_used_signature: policy
+                .check_raffle_used_signature
+                .unwrap_or(self.check_raffle_used_signature),
+            sol_wallet: policy.sol_wallet.clone().or_else(|| self.sol_wallet.clone()),
+            tokens: self.tokens.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SolscanSettings {
+    pub api_url: String,
+    /// Seconds a transaction lookup may take before the ticket fails with `upstream_error`.
+    pub timeout_secs: u64,
+}
+
+impl Default for SolscanSettings {
+    fn default() -> Self {
+        Self {
+            api_url: "https://public-api.solscan.io".to_string(),
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// Requests per window, 0 disables a limit.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    pub key: u32,
+    pub user_tickets: u32,
+    pub window_secs: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            key: 120,
+            user_tickets: 5,
+            window_secs: 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    pub max_attempts: u32,
+    pub retry_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            retry_secs: 10,
+            timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdempotencySettings {
+    /// Seconds an `Idempotency-Key` is remembered.
+    pub ttl_secs: i64,
+}
+
+impl Default for IdempotencySettings {
+    fn default() -> Self {
+        Self { ttl_secs: 24 * 60 * 60 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordSettings {
+    /// Webhook URLs raffle events are announced to.
+    pub webhook_urls: Vec<String>,
+    /// Event types announced, empty announces all.
+    pub events: Vec<EventKind>,
+    /// Hex public key of the application whose slash commands are answered.
+    pub public_key: Option<String>,
+    pub api_url: String,
+}
+
+impl Default for DiscordSettings {
+    fn default() -> Self {
+        Self {
+            webhook_urls: Vec::new(),
+            events: Vec::new(),
+            public_key: None,
+            api_url: "https://discord.com/api/v10".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthSettings {
+    /// Whether `GET /readyz` also requires Solscan to answer.
+    pub check_solscan: bool,
+}
+
+/// Bearer tokens accepted besides the keys stored in the database.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSettings {
+    /// Token of a key with every scope.
+    pub bearer_token: Option<String>,
+    /// Keys by name.
+    pub keys: BTreeMap<String, KeySettings>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeySettings {
+    pub token: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// JWT bearer tokens, rejected unless `algorithm` is set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtSettings {
+    /// `HS256`, `RS256` or `EdDSA`.
+    pub algorithm: Option<String>,
+    /// Key of HS256.
+    pub secret: Option<String>,
+    /// PEM key of RS256 and EdDSA, or a path to it; `load` replaces a path with the file.
+    pub public_key: Option<String>,
+    pub audience: Option<String>,
+    pub issuer: Option<String>,
+    /// Scopes granted to the JWTs with each role.
+    pub roles: BTreeMap<String, Vec<Scope>>,
+}
+
+impl Settings {
+    /// Loads and validates the settings for the command line `args` (without the program
+    /// name) and the environment variables `env`.
+    pub fn load(args: &[String], env: &HashMap<String, String>) -> Result<Self, Error> {
+        let mut file = None;
+        let mut overrides = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next().cloned().context(UsageSnafu {
+                    message: format!("{} needs a value", arg),
+                })
+            };
+            match arg.as_str() {
+                "--config" => file = Some(value()?),
+                "--set" => {
+                    let value = value()?;
+                    let (key, value) = value.split_once('=').context(UsageSnafu {
+                        message: format!("--set {} is not <key>=<value>", value),
+                    })?;
+                    overrides.push((key.to_string(), value.to_string()));
+                }
+                _ => {
+                    return UsageSnafu {
+                        message: format!("Unknown argument {}", arg),
+                    }
+                    .fail()
+                }
+            }
+        }
+
+        let mut builder = Config::builder();
+        match file.or_else(|| env.get("RAFFLE_CONFIG").cloned()) {
+            Some(path) => {
+                ensure!(Path::new(&path).exists(), MissingFileSnafu { path });
+                builder = builder.add_source(File::new(&path, FileFormat::Toml));
+            }
+            None => {
+                builder = builder.add_source(File::new(DEFAULT_FILE, FileFormat::Toml).required(false))
+            }
+        }
+        let list = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+        for (name, reason) in REMOVED_ENV.iter().filter(|(name, _)| env.contains_key(*name)) {
+            warn!("{} is deprecated and ignored: {}", name, reason);
+        }
+        for (name, key, is_list) in ENV_OVERRIDES {
+            let Some(value) = env.get(name) else { continue };
+            builder = if is_list {
+                builder.set_override(key, list(value))
+            } else {
+                builder.set_override(key, value.as_str())
+            }
+            .context(LoadSnafu)?;
+        }
+        for name in list(env.get("API_KEYS").map_or("", String::as_str)) {
+            let var = format!("API_KEY_{}", name.to_uppercase());
+            let token = env.get(&var).cloned().unwrap_or_default();
+            let scopes = list(env.get(&format!("{}_SCOPES", var)).map_or("", String::as_str));
+            builder = builder
+                .set_override(format!("auth.keys.{}.token", name), token)
+                .and_then(|builder| builder.set_override(format!("auth.keys.{}.scopes", name), scopes))
+                .context(LoadSnafu)?;
+        }
+        for (var, value) in env {
+            let Some(role) = var.strip_prefix("JWT_ROLE_").and_then(|var| var.strip_suffix("_SCOPES"))
+            else {
+                continue;
+            };
+            let key = format!("jwt.roles.{}", role.to_lowercase());
+            builder = builder.set_override(key, list(value)).context(LoadSnafu)?;
+        }
+        for (key, value) in overrides {
+            builder = builder.set_override(key, value).context(LoadSnafu)?;
+        }
+        let mut settings: Settings = builder
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .context(LoadSnafu)?;
+
+        if let Some(path) = settings.jwt.public_key.clone().filter(|key| !key.contains("-----BEGIN")) {
+            let pem = fs::read_to_string(&path).map_err(|err| Error::Invalid {
+                problems: vec![format!("jwt.public_key: could not read {}: {}", path, err)],
+            })?;
+            settings.jwt.public_key = Some(pem);
+        }
+        let problems = settings.problems();
+        ensure!(problems.is_empty(), InvalidSnafu { problems });
+        Ok(settings)
+    }
+
+    /// Everything wrong with the settings, checked once at startup.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut problem = |key: &str, message: &str| problems.push(format!("{}: {}", key, message));
+        let is_url = |url: &str| {
+            reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        };
+
+        if self.server.ip.parse::<IpAddr>().is_err() {
+            problem("server.ip", "must be an IP address");
+        }
+        if self.server.port == 0 {
+            problem("server.port", "must not be 0");
+        }
+        for (key, path) in [
+            ("server.cert_file", &self.server.cert_file),
+            ("server.key_file", &self.server.key_file),
+        ] {
+            if !Path::new(path).is_file() {
+                problem(key, &format!("{} does not exist", path));
+            }
+        }
+
+        match self.database.backend.as_str() {
+            "mongo" if self.database.mongodb_uri.is_none() => {
+                problem("database.mongodb_uri", "must be set for the mongo backend")
+            }
+            "postgres" | "sqlite" if self.database.url.is_none() => {
+                problem("database.url", "must be set for the postgres and sqlite backends")
+            }
+            "mongo" | "postgres" | "sqlite" => {}
+            _ => problem("database.backend", "must be mongo, postgres or sqlite"),
+        }
+
+        if self.validation.check_raffle_destination && self.validation.sol_wallet.is_none() {
+            problem("validation.sol_wallet", "must be set with check_raffle_destination");
+        }
+        if self.validation.tokens.iter().all(|token| token.trim().is_empty()) {
+            problem("validation.tokens", "must list at least one token");
+        }
+        if !is_url(&self.solscan.api_url) {
+            problem("solscan.api_url", "must be an http(s) URL");
+        }
+        if self.solscan.timeout_secs == 0 {
+            problem("solscan.timeout_secs", "must not be 0");
+        }
+        if self.rate_limit.window_secs == 0 {
+            problem("rate_limit.window_secs", "must not be 0");
+        }
+        if self.webhooks.max_attempts == 0 {
+            problem("webhooks.max_attempts", "must not be 0");
+        }
+        if self.idempotency.ttl_secs <= 0 {
+            problem("idempotency.ttl_secs", "must be positive");
+        }
+
+        if !self.discord.webhook_urls.iter().all(|url| is_url(url)) {
+            problem("discord.webhook_urls", "must be http(s) URLs");
+        }
+        let public_key = self.discord.public_key.as_deref().map(hex::decode);
+        if public_key.is_some_and(|key| key.map_or(true, |key| key.len() != 32)) {
+            problem("discord.public_key", "must be 32 hex encoded bytes");
+        }
+        if !is_url(&self.discord.api_url) {
+            problem("discord.api_url", "must be an http(s) URL");
+        }
+
+        if self.auth.bearer_token.as_deref() == Some("") {
+            problem("auth.bearer_token", "must not be empty");
+        }
+        for (name, key) in &self.auth.keys {
+            if key.token.is_empty() {
+                problem(&format!("auth.keys.{}.token", name), "must be set");
+            }
+        }
+        if let Err(err) = JwtConfig::from_settings(&self.jwt) {
+            problem("jwt", &err.to_string());
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<Settings, Error> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let env: HashMap<String, String> =
+            env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        Settings::load(&args, &env)
+    }
+
+    #[test]
+    fn file_env_and_flags_are_layered() {
+        let dir = std::env::temp_dir().join(format!("raffle-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, file) = (dir.join("cert.pem"), dir.join("settings.toml"));
+        std::fs::write(&cert, "").unwrap();
+        write!(
+            std::fs::File::create(&file).unwrap(),
+            "[server]\nport = 9000\ncert_file = {cert:?}\nkey_file = {cert:?}\n\
+             [database]\nbackend = \"sqlite\"\nurl = \"sqlite::memory:\"\nname = \"FromFile\"\n\
+             [validation]\ncheck_tx_status = true\n",
+            cert = cert.to_str().unwrap()
+        )
+        .unwrap();
+
+        let settings = load(
+            &["--config", file.to_str().unwrap(), "--set", "rate_limit.key=7"],
+            &[
+                ("SERVER_PORT", "9001"),
+                ("COLL_TICKET", "Tickets"),
+                ("RAFFLE_TOKENS", "USDC, SOL"),
+                ("DISCORD_EVENTS", "raffle.opened,raffle.drawn"),
+                ("RATE_LIMIT_KEY", "8"),
+                ("API_KEYS", "bot"),
+                ("API_KEY_BOT", "bot-token"),
+                ("API_KEY_BOT_SCOPES", "read, ticket:submit"),
+                ("JWT_ALGORITHM", "HS256"),
+                ("JWT_SECRET", "secret"),
+                ("JWT_AUDIENCE", "raffle-api"),
+                ("JWT_ROLE_ADMIN_SCOPES", "raffle:admin"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(settings.server.port, 9001);
+        assert_eq!(settings.database.name, "FromFile");
+        assert_eq!(settings.database.collections.ticket, "Tickets");
+        assert_eq!(settings.database.collections.raffle, "Raffle");
+        assert!(settings.validation.check_tx_status);
+        assert_eq!(settings.validation.tokens, ["USDC", "SOL"]);
+        assert_eq!(settings.discord.events, [EventKind::RaffleOpened, EventKind::RaffleDrawn]);
+        assert_eq!(settings.rate_limit.key, 7);
+        assert_eq!(settings.auth.keys["bot"].token, "bot-token");
+        assert_eq!(settings.auth.keys["bot"].scopes, [Scope::Read, Scope::TicketSubmit]);
+        assert_eq!(settings.jwt.roles["admin"], [Scope::RaffleAdmin]);
+
+        let err = load(
+            &["--config", file.to_str().unwrap()],
+            &[("DB_BACKEND", "redis"), ("SERVER_IP", "x"), ("RAFFLE_TOKENS", "")],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("server.ip: must be an IP address"), "{}", err);
+        assert!(err.contains("database.backend: must be mongo, postgres or sqlite"), "{}", err);
+        assert!(err.contains("validation.tokens: must list at least one token"), "{}", err);
+
+        let err = load(
+            &["--config", file.to_str().unwrap()],
+            &[("API_KEYS", "bot"), ("JWT_ALGORITHM", "HS256")],
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("auth.keys.bot.token: must be set"), "{}", err);
+        assert!(err.contains("jwt: jwt.secret must be set for HS256"), "{}", err);
+        let err = load(&["--config", file.to_str().unwrap()], &[("API_KEY_X_SCOPES", "all")]);
+        assert!(err.is_ok(), "keys not named in API_KEYS are ignored");
+        let err = load(&["--config", file.to_str().unwrap()], &[("JWT_ROLE_X_SCOPES", "all")])
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown scope 'all'"), "{}", err);
+
+        let err = load(&["--config", file.to_str().unwrap(), "--set", "server.nope=1"], &[]).unwrap_err();
+        assert!(err.to_string().contains("nope"), "{}", err);
+        assert!(matches!(load(&["--verbose"], &[]), Err(Error::Usage { .. })));
+        assert!(matches!(load(&["--config", "/missing.toml"], &[]), Err(Error::MissingFile { .. })));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
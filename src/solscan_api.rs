@@ -1,8 +1,35 @@
+use std::time::{Duration, Instant};
+
 use actix_web::http::StatusCode;
+use lazy_static::lazy_static;
 use log::info;
 use rust_decimal::prelude::*;
+use snafu::prelude::*;
+
+use crate::metrics::{METRICS, SOLSCAN_DURATION, SOLSCAN_REQUESTS};
+use crate::settings::SolscanSettings;
 
+lazy_static! {
+    /// Shared by every lookup, so connections are reused; timeouts are set per request.
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
 
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Solscan request failed: {source}"))]
+    Request { source: reqwest::Error },
+    #[snafu(display("Solscan returned {status}"))]
+    Status { status: StatusCode },
+    #[snafu(display("Solscan returned invalid JSON: {source}"))]
+    InvalidJson { source: json::Error },
+    #[snafu(display("Solscan response has no valid '{field}'"))]
+    MissingField { field: &'static str },
+    #[snafu(display("Transferred amount is out of range: {source}"))]
+    InvalidAmount { source: rust_decimal::Error },
+}
+
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub struct SolanaTX {
     tx_signature: String,
@@ -15,45 +42,120 @@ pub struct SolanaTX {
     pub status: String,
 }
 
-pub async fn get_solana_tx(tx_signature: String) -> Result<SolanaTX, StatusCode> {
-    let client = reqwest::Client::new();
-    let url = "https://public-api.solscan.io/transaction/".to_owned() + tx_signature.as_str();
-    let result = client
+pub async fn get_solana_tx(settings: &SolscanSettings, tx_signature: String) -> Result<SolanaTX, Error> {
+    let start = Instant::now();
+    let result = fetch_solana_tx(settings, tx_signature).await;
+    METRICS.observe(SOLSCAN_DURATION, vec![], start.elapsed());
+    let outcome = if result.is_ok() { "ok" } else { "error" };
+    METRICS.inc(SOLSCAN_REQUESTS, vec![("outcome", outcome.to_string())]);
+    result
+}
+
+async fn fetch_solana_tx(settings: &SolscanSettings, tx_signature: String) -> Result<SolanaTX, Error> {
+    let url = format!("{}/transaction/{}", settings.api_url, tx_signature);
+    let result = CLIENT
         .get(url.clone())
         .header("User-Agent", "Mozilla/5.0")
+        .timeout(Duration::from_secs(settings.timeout_secs))
         .send()
         .await
-        .unwrap();
+        .context(RequestSnafu)?;
 
     info!("{}", url);
     match result.status() {
         StatusCode::OK => {
-            let json = json::parse(result.text().await.unwrap().as_str()).unwrap();
-            println!("{}", json);
-            let tx = SolanaTX {
-                tx_signature: json["txHash"].to_string(),
-                block_time: json["blockTime"].to_string().parse::<i64>().unwrap(),
-                source_owner: json["tokenTransfers"][0]["source_owner"].to_string(),
-                destination_owner: json["tokenTransfers"][0]["destination_owner"].to_string(),
-                token_address: json["tokenTransfers"][0]["token"]["address"].to_string(),
-                token_symbol: json["tokenTransfers"][0]["token"]["symbol"].to_string(),
-                amount: Decimal::new(
-                    json["tokenTransfers"][0]["amount"]
-                        .to_string()
-                        .parse::<i64>()
-                        .unwrap_or_default(),
-                    json["tokenTransfers"][0]["token"]["decimals"]
-                        .to_string()
-                        .parse::<u32>()
-                        .unwrap_or_default(),
-                )
-                .to_f32()
-                .unwrap(),
-                status: json["status"].to_string(),
-            };
-            println!("{:?}", tx);
+            let tx = parse_solana_tx(&result.text().await.context(RequestSnafu)?)?;
+            info!("{:?}", tx);
             Ok(tx)
         }
-        _ => Err(result.status()),
+        status => StatusSnafu { status }.fail(),
+    }
+}
+
+/// Checks that Solscan answers at all; only server errors and failed requests count as down.
+pub async fn ping(api_url: &str, timeout: Duration) -> Result<(), Error> {
+    let result = CLIENT
+        .get(api_url)
+        .header("User-Agent", "Mozilla/5.0")
+        .timeout(timeout)
+        .send()
+        .await
+        .context(RequestSnafu)?;
+    match result.status() {
+        status if status.is_server_error() => StatusSnafu { status }.fail(),
+        _ => Ok(()),
+    }
+}
+
+/// Parses a Solscan `/transaction` response.
+pub fn parse_solana_tx(body: &str) -> Result<SolanaTX, Error> {
+    let json = json::parse(body).context(InvalidJsonSnafu)?;
+    Ok(SolanaTX {
+        tx_signature: json["txHash"].to_string(),
+        block_time: json["blockTime"]
+            .to_string()
+            .parse::<i64>()
+            .ok()
+            .context(MissingFieldSnafu { field: "blockTime" })?,
+        source_owner: json["tokenTransfers"][0]["source_owner"].to_string(),
+        destination_owner: json["tokenTransfers"][0]["destination_owner"].to_string(),
+        token_address: json["tokenTransfers"][0]["token"]["address"].to_string(),
+        token_symbol: json["tokenTransfers"][0]["token"]["symbol"].to_string(),
+        // `decimals` beyond 28 can't be represented and is rejected
+        amount: Decimal::try_new(
+            json["tokenTransfers"][0]["amount"]
+                .to_string()
+                .parse::<i64>()
+                .unwrap_or_default(),
+            json["tokenTransfers"][0]["token"]["decimals"]
+                .to_string()
+                .parse::<u32>()
+                .unwrap_or_default(),
+        )
+        .context(InvalidAmountSnafu)?
+        .to_f32()
+        .unwrap_or_default(),
+        status: json["status"].to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_token_transfer() {
+        let tx = parse_solana_tx(
+            r#"{"txHash":"abc","blockTime":1650000000,"status":"Success","tokenTransfers":[
+                {"source_owner":"src","destination_owner":"dst","amount":"2500000",
+                 "token":{"address":"mint","symbol":"USDC","decimals":6}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(tx.block_time, 1650000000);
+        assert_eq!(tx.token_symbol, "USDC");
+        assert_eq!(tx.destination_owner, "dst");
+        assert_eq!(tx.amount, 2.5);
+
+        let err = parse_solana_tx(
+            r#"{"blockTime":1650000000,"tokenTransfers":[{"amount":"1","token":{"decimals":29}}]}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidAmount { .. }), "{}", err);
+    }
+
+    #[test]
+    fn rejects_broken_json() {
+        assert!(matches!(
+            parse_solana_tx("{\"txHash\": "),
+            Err(Error::InvalidJson { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_block_time() {
+        assert!(matches!(
+            parse_solana_tx(r#"{"txHash":"abc","status":"Success"}"#),
+            Err(Error::MissingField { field: "blockTime" })
+        ));
     }
 }
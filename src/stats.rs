@@ -0,0 +1,90 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::{Buyer, PrizeOdds, Raffle, RaffleStats, Ticket, UserOdds};
+
+/// Summarizes the tickets sold for `raffle`, listing up to `top` buyers.
+pub fn raffle_stats(raffle: &Raffle, tickets: &[Ticket], top: usize) -> RaffleStats {
+    let mut per_user: HashMap<&str, u32> = HashMap::new();
+    let mut revenue = BTreeMap::new();
+    let mut tickets_sold = 0;
+    for ticket in tickets {
+        tickets_sold += ticket.amount as u32;
+        *per_user.entry(ticket.username.as_str()).or_default() += ticket.amount as u32;
+        *revenue
+            .entry(raffle.ticket_token_name.clone())
+            .or_insert(0.0) += ticket.amount_send;
+    }
+
+    let mut top_buyers: Vec<Buyer> = per_user
+        .iter()
+        .map(|(username, tickets)| Buyer {
+            username: username.to_string(),
+            tickets: *tickets,
+        })
+        .collect();
+    top_buyers.sort_by(|a, b| {
+        b.tickets
+            .cmp(&a.tickets)
+            .then_with(|| a.username.cmp(&b.username))
+    });
+    top_buyers.truncate(top);
+
+    let tickets_total = raffle.ticket_amount as u32;
+    RaffleStats {
+        raffle_id: raffle.id,
+        tickets_total,
+        tickets_sold,
+        tickets_remaining: tickets_total.saturating_sub(tickets_sold),
+        participants: per_user.len() as u32,
+        revenue,
+        top_buyers,
+    }
+}
+
+/// Odds of `username` in `raffle`. Prizes are drawn in order, each picking one of the sold
+/// tickets that has not been drawn yet, so every prize is won with probability
+/// `tickets / tickets_sold` as long as enough tickets were sold to award it.
+pub fn user_odds(raffle: &Raffle, tickets: &[Ticket], username: &str) -> UserOdds {
+    let tickets_sold: u32 = tickets.iter().map(|t| t.amount as u32).sum();
+    let user_tickets: u32 = tickets
+        .iter()
+        .filter(|t| t.username == username)
+        .map(|t| t.amount as u32)
+        .sum();
+    let share = if tickets_sold == 0 {
+        0.0
+    } else {
+        user_tickets as f64 / tickets_sold as f64
+    };
+
+    let prize_names = if raffle.prizes.is_empty() {
+        vec![raffle.title.clone()]
+    } else {
+        raffle.prizes.clone()
+    };
+    let prizes = prize_names
+        .into_iter()
+        .enumerate()
+        .map(|(draw, prize)| PrizeOdds {
+            prize,
+            probability: if (draw as u32) < tickets_sold { share } else { 0.0 },
+        })
+        .collect::<Vec<_>>();
+
+    // 1 - P(none of the awarded draws hits one of the user's tickets)
+    let draws = (prizes.len() as u32).min(tickets_sold);
+    let others = (tickets_sold - user_tickets) as f64;
+    let miss_all = (0..draws).fold(1.0, |p, i| {
+        p * (others - i as f64).max(0.0) / (tickets_sold - i) as f64
+    });
+
+    UserOdds {
+        raffle_id: raffle.id,
+        username: username.to_string(),
+        tickets: user_tickets,
+        tickets_sold,
+        share,
+        prizes,
+        any_prize: if user_tickets == 0 { 0.0 } else { 1.0 - miss_all },
+    }
+}
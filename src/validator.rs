@@ -1,169 +1,287 @@
-use std::env;
-
+use actix_web::http::StatusCode;
 use log::info;
-use mongodb::bson::oid::ObjectId;
-use mongodb::Client;
-use rust_decimal::prelude::ToPrimitive;
-use snafu::{prelude::*, Whatever};
+use snafu::prelude::*;
 
-use crate::{DatabaseRaffle, solscan_api, Ticket};
+use crate::error::{ApiError, InvalidRaffleSnafu};
+use crate::metrics::{METRICS, TICKET_VALIDATIONS};
+use crate::model::EventKind;
+use crate::settings::{Settings, ValidationSettings};
+use crate::{repository, solscan_api, Raffle, Ticket, Violation};
+use crate::repository::{tickets_left, RaffleRepository};
 use crate::solscan_api::SolanaTX;
 
-pub async fn validate_ticket(
-    client: &Client,
-    db_interface: &DatabaseRaffle,
-    ticket: Ticket,
-) -> Result<u16, Whatever> {
-    let tx = solscan_api::get_solana_tx(ticket.spl_tx_signature.clone()).await;
+/// Reasons a ticket is rejected.
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum Error {
+    #[snafu(display("Wrong token send in TX"))]
+    WrongToken,
+    #[snafu(display("SPL TX status not valid"))]
+    TxStatusInvalid,
+    #[snafu(display("Raffle does not exist"))]
+    RaffleNotFound,
+    #[snafu(display("Raffle is not running"))]
+    RaffleNotRunning,
+    #[snafu(display("DateTime invalid"))]
+    DateTimeInvalid,
+    #[snafu(display("Destination invalid"))]
+    DestinationInvalid,
+    #[snafu(display("SPL Signature already used"))]
+    SignatureUsed,
+    #[snafu(display("Ticket amount would be 0"))]
+    ZeroTickets,
+    #[snafu(display("Transferred amount invalid"))]
+    AmountInvalid,
+    #[snafu(display("API-Error {source}"))]
+    Upstream { source: solscan_api::Error },
+    #[snafu(display("{source}"))]
+    Storage { source: repository::Error },
+}
 
-    info!("username={}", ticket.username);
+impl Error {
+    /// Machine-readable identifier used in API error bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::WrongToken => "wrong_token",
+            Error::TxStatusInvalid => "tx_status_invalid",
+            Error::RaffleNotFound => "raffle_not_found",
+            Error::RaffleNotRunning => "raffle_not_running",
+            Error::DateTimeInvalid => "tx_time_invalid",
+            Error::DestinationInvalid => "destination_invalid",
+            Error::SignatureUsed => "signature_used",
+            Error::ZeroTickets => "zero_tickets",
+            Error::AmountInvalid => "amount_invalid",
+            Error::Upstream { .. } => "upstream_error",
+            Error::Storage { .. } => "storage_error",
+        }
+    }
 
-    match tx {
-        Ok(tx) => {
-            info!("{:?}", tx);
-            // Validate Ticket
-            // Check if raffle_id is valid
-            if env::var("CHECK_TOKEN_SYMBOL").unwrap_or_default().parse::<bool>().unwrap_or(false) && !check_token(client, db_interface, ticket.raffle_id, &tx).await {
-                whatever!("Wrong token send in TX")
-            };
-
-            if env::var("CHECK_TX_STATUS").unwrap_or_default().parse::<bool>().unwrap_or(false) && !tx.status.contains("Success"){
-                whatever!("SPL TX status not valid")
-            };
-
-            if env::var("CHECK_RAFFLE_EXISTS").unwrap_or_default().parse::<bool>().unwrap_or(false) && !check_if_raffle_exists(client, db_interface, ticket.raffle_id).await {
-                whatever!("Raffle does not exist")
-            };
-            // Check if raffle is running
-            if env::var("CHECK_RAFFLE_RUNNING").unwrap_or_default().parse::<bool>().unwrap_or(false) && !check_if_raffle_is_running(client, db_interface, ticket.raffle_id).await {
-                whatever!("Raffle is not running")
-            };
-
-            // Check if date_time is valid
-            if env::var("CHECK_RAFFLE_TIME").unwrap_or_default().parse::<bool>().unwrap_or(false) && !check_if_past_raffle_create(client, db_interface, ticket.raffle_id, &tx).await {
-                whatever!("DateTime invalid")
-            };
-
-            // Check if tx_destination is valid
-            if env::var("CHECK_RAFFLE_DESTINATION").unwrap_or_default().parse::<bool>().unwrap_or(false) && !check_if_tx_destination_valid(&tx).await {
-                whatever!("Destination invalid")
-            };
-
-            // Check if spl_tx_signature is used
-            if env::var("CHECK_RAFFLE_USED_SIGNATURE").unwrap_or_default().parse::<bool>().unwrap_or(false) && check_if_spl_signature_is_used(client, db_interface, &ticket.spl_tx_signature).await {
-                whatever!("SPL Signature already used")
-            };
-
-
-            // Calculate valid ticket amount
-            let tickets =
-                calculate_ticket_amount(client, db_interface, ticket.raffle_id, tx.amount).await;
-            if tickets == 0 {
-                whatever!("Ticket amount would be 0")
-            };
-            Ok(tickets)
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::RaffleNotFound => StatusCode::NOT_FOUND,
+            Error::RaffleNotRunning | Error::SignatureUsed | Error::ZeroTickets => {
+                StatusCode::CONFLICT
+            }
+            Error::Upstream { .. } => StatusCode::BAD_GATEWAY,
+            Error::Storage { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
         }
-        Err(e) => whatever!("API-Error {}", e),
     }
 }
 
-async fn check_if_raffle_exists(
-    client: &Client,
-    db_interface: &DatabaseRaffle,
-    oid: ObjectId,
-) -> bool {
-    let raffle = db_interface.get_raffle_by_id(client, oid).await.unwrap();
-    !raffle.is_empty()
+/// Checks the ticket's SPL transaction and returns how many tickets it buys.
+/// The transferred amount is recorded in `ticket.amount_send`.
+pub async fn validate_ticket(
+    db_interface: &dyn RaffleRepository,
+    settings: &Settings,
+    ticket: &mut Ticket,
+) -> Result<u16, Error> {
+    let result = check_ticket(db_interface, settings, ticket).await;
+    let outcome = match &result {
+        Ok(_) => "valid",
+        Err(err) => err.code(),
+    };
+    METRICS.inc(TICKET_VALIDATIONS, vec![("outcome", outcome.to_string())]);
+    result
 }
 
-async fn check_if_raffle_is_running(
-    client: &Client,
-    db_interface: &DatabaseRaffle,
-    oid: ObjectId,
-) -> bool {
-    let raffle = db_interface.get_raffle_by_id(client, oid).await.unwrap();
-    raffle[0].status.contains("running")
-}
+async fn check_ticket(
+    db_interface: &dyn RaffleRepository,
+    settings: &Settings,
+    ticket: &mut Ticket,
+) -> Result<u16, Error> {
+    // Every check needs the raffle, so it has to exist before Solscan is asked
+    let raffle = db_interface
+        .get_raffle_by_id(ticket.raffle_id)
+        .await
+        .context(StorageSnafu)?
+        .pop()
+        .context(RaffleNotFoundSnafu)?;
+    let checks = settings.validation.for_raffle(&raffle.validation);
 
-async fn check_if_past_raffle_create(client: &Client,
-                                     db_interface: &DatabaseRaffle,
-                                     oid: ObjectId,
-                                     tx: &SolanaTX) -> bool {
-    let raffle = db_interface.get_raffle_by_id(client, oid).await.unwrap();
-    tx.block_time > raffle[0].date_created
-}
+    let tx = solscan_api::get_solana_tx(&settings.solscan, ticket.spl_tx_signature.clone())
+        .await
+        .map_err(|err| match err {
+            solscan_api::Error::InvalidAmount { .. } => Error::AmountInvalid,
+            source => Error::Upstream { source },
+        })?;
 
+    info!("username={}", ticket.username);
+    info!("{:?}", tx);
+
+    // Validate Ticket
+    if checks.check_token_symbol && !check_token(&raffle, &tx) {
+        return WrongTokenSnafu.fail();
+    };
+
+    if checks.check_tx_status && !tx.status.contains("Success") {
+        return TxStatusInvalidSnafu.fail();
+    };
+
+    // Check if raffle is running
+    if checks.check_raffle_running && !check_if_raffle_is_running(&raffle) {
+        return RaffleNotRunningSnafu.fail();
+    };
+
+    // Check if date_time is valid
+    if checks.check_raffle_time && !check_if_past_raffle_create(&raffle, &tx) {
+        return DateTimeInvalidSnafu.fail();
+    };
+
+    // Check if tx_destination is valid
+    if checks.check_raffle_destination
+        && !check_if_tx_destination_valid(checks.sol_wallet.as_deref(), &tx) {
+        return DestinationInvalidSnafu.fail();
+    };
 
-async fn check_token(client: &Client,
-                     db_interface: &DatabaseRaffle,
-                     oid: ObjectId,
-                     tx: &SolanaTX) -> bool {
-    let raffle = db_interface.get_raffle_by_id(client,oid).await.unwrap();
+    // Check if spl_tx_signature is used
+    if checks.check_raffle_used_signature
+        && check_if_spl_signature_is_used(db_interface, &ticket.spl_tx_signature).await?
+    {
+        return SignatureUsedSnafu.fail();
+    };
 
-    raffle[0].ticket_token_name.contains(&tx.token_symbol)
+    // Calculate valid ticket amount
+    let tickets = calculate_ticket_amount(db_interface, &raffle, tx.amount).await?;
+    if tickets == 0 {
+        return ZeroTicketsSnafu.fail();
+    };
+    ticket.amount_send = tx.amount;
+    Ok(tickets)
 }
 
+fn check_if_raffle_is_running(raffle: &Raffle) -> bool {
+    raffle.status.contains("running")
+}
+
+fn check_if_past_raffle_create(raffle: &Raffle, tx: &SolanaTX) -> bool {
+    tx.block_time > raffle.date_created
+        && raffle.date_start.is_none_or(|start| tx.block_time >= start)
+        && raffle.date_end.is_none_or(|end| tx.block_time <= end)
+}
+
+fn check_token(raffle: &Raffle, tx: &SolanaTX) -> bool {
+    raffle.ticket_token_name.contains(&tx.token_symbol)
+}
 
-async fn check_if_tx_destination_valid(tx: &SolanaTX) -> bool {
-    tx.destination_owner.contains(&env::var("SOL_WALLET").unwrap().to_string())
+fn check_if_tx_destination_valid(wallet: Option<&str>, tx: &SolanaTX) -> bool {
+    match wallet {
+        Some(wallet) if !wallet.is_empty() => tx.destination_owner.contains(wallet),
+        _ => false,
+    }
 }
 
 async fn check_if_spl_signature_is_used(
-    client: &Client,
-    db_interface: &DatabaseRaffle,
-    spl_signature: &String,
-) -> bool {
+    db_interface: &dyn RaffleRepository,
+    spl_signature: &str,
+) -> Result<bool, Error> {
     let result = db_interface
-        .get_spl_tx_in_ticket(client, spl_signature)
+        .get_spl_tx_in_ticket(spl_signature)
         .await
-        .unwrap();
-    match result {
-        Some(_result) => true,
-        None => false,
-    }
+        .context(StorageSnafu)?;
+    Ok(result.is_some())
 }
 
 async fn calculate_ticket_amount(
-    client: &Client,
-    db_interface: &DatabaseRaffle,
-    raffle_id: ObjectId,
+    db_interface: &dyn RaffleRepository,
+    raffle: &Raffle,
     usdc_amount: f32,
-) -> u16 {
-    let mut raffle = db_interface
-        .get_raffle_by_id(client, raffle_id)
-        .await
-        .unwrap();
-
+) -> Result<u16, Error> {
     let tickets = db_interface
-        .get_tickets_by_id_raffle(client, raffle_id.clone())
+        .get_tickets_by_id_raffle(raffle.id)
         .await
-        .unwrap();
+        .context(StorageSnafu)?;
 
-    let mut sold_tickets = 0;
+    let mut sold_tickets: u16 = 0;
     for ticket in tickets {
-        sold_tickets += ticket.amount
+        sold_tickets = sold_tickets.saturating_add(ticket.amount)
     }
 
     info!("input_usdc_amount={:?}", usdc_amount);
-    info!("total_tickets={:?}", raffle[0].ticket_amount);
+    info!("total_tickets={:?}", raffle.ticket_amount);
     info!("sold_tickets={:?}", sold_tickets);
-    info!("ticket_price={:?}", raffle[0].ticket_price);
+    info!("ticket_price={:?}", raffle.ticket_price);
 
-    let input_value_ticket = usdc_amount / raffle[0].ticket_price;
-    let tickets_left = raffle[0].ticket_amount - sold_tickets;
+    let input_value_ticket = usdc_amount / raffle.ticket_price;
 
     info!("input_value_ticket={:?}", input_value_ticket);
-    info!("tickets_left={:?}", tickets_left);
-
-    if tickets_left > 0 {
-        if input_value_ticket <= tickets_left.to_f32().unwrap() {
-            raffle[0].status = "closed".to_string();
-            db_interface.update_raffle(client, &mut raffle[0]);
-            input_value_ticket as u16
-        } else {
-            tickets_left as u16
+
+    // The final amount is capped again by `allocate_ticket` when the ticket is stored.
+    Ok(tickets_left(raffle, sold_tickets, input_value_ticket as u16))
+}
+
+//region === Raffle ===
+/// Upper bound for `ticket_amount`, far above any real raffle.
+const MAX_TICKET_AMOUNT: u16 = 10_000;
+
+/// Fails with [`ApiError::InvalidRaffle`] listing every rule `raffle` breaks.
+pub fn check_raffle(raffle: &Raffle, settings: &ValidationSettings) -> Result<(), ApiError> {
+    let violations = validate_raffle(raffle, settings);
+    ensure!(violations.is_empty(), InvalidRaffleSnafu { violations });
+    Ok(())
+}
+
+/// Checks a raffle payload and returns every broken rule.
+pub fn validate_raffle(raffle: &Raffle, settings: &ValidationSettings) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut violation = |field: &str, message: String| {
+        violations.push(Violation {
+            field: field.to_string(),
+            message,
+        })
+    };
+
+    if raffle.title.trim().is_empty() {
+        violation("title", "must not be empty".to_string());
+    }
+    if !(raffle.ticket_price.is_finite() && raffle.ticket_price > 0.0) {
+        violation("ticket_price", "must be greater than 0".to_string());
+    }
+    if raffle.ticket_amount == 0 || raffle.ticket_amount > MAX_TICKET_AMOUNT {
+        violation(
+            "ticket_amount",
+            format!("must be between 1 and {}", MAX_TICKET_AMOUNT),
+        );
+    }
+    if raffle.prizes.len() > raffle.ticket_amount as usize {
+        violation("prizes", "must not outnumber the tickets".to_string());
+    }
+    if raffle.ticket_token_name.trim().is_empty() {
+        violation("ticket_token_name", "must not be empty".to_string());
+    } else if !settings.tokens.contains(&raffle.ticket_token_name) {
+        violation(
+            "ticket_token_name",
+            format!("must be one of {}", settings.tokens.join(", ")),
+        );
+    }
+    for (field, date) in [("date_start", raffle.date_start), ("date_end", raffle.date_end)] {
+        if date.is_some_and(|date| date <= 0) {
+            violation(field, "must be a positive unix time".to_string());
         }
-    } else {
-        0.0 as u16
     }
+    if let (Some(start), Some(end)) = (raffle.date_start, raffle.date_end) {
+        if end <= start {
+            violation("date_end", "must be after date_start".to_string());
+        }
+    }
+    for (event, template) in &raffle.announcements {
+        let field = format!("announcements.{}", event);
+        if let Err(message) = event.parse::<EventKind>() {
+            violation(&field, message);
+        } else if template.title.trim().is_empty() {
+            violation(&field, "title must not be empty".to_string());
+        }
+    }
+    let checks = settings.for_raffle(&raffle.validation);
+    if raffle.validation.sol_wallet.as_ref().is_some_and(|wallet| wallet.trim().is_empty()) {
+        violation("validation.sol_wallet", "must not be empty".to_string());
+    } else if checks.check_raffle_destination && checks.sol_wallet.is_none() {
+        violation(
+            "validation.sol_wallet",
+            "must be set with check_raffle_destination".to_string(),
+        );
+    }
+    violations
 }
+
+//endregion
@@ -0,0 +1,383 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use rand::RngCore;
+use sha2::Sha256;
+use snafu::prelude::*;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::audit;
+use crate::auth::{ApiKey, Scope};
+use crate::error::{ApiError, InvalidIdSnafu, InvalidRequestSnafu};
+use crate::events::EventBus;
+use crate::model::*;
+use crate::repository::{PageRequest, RaffleRepository, StoredWebhook};
+use crate::settings::WebhookSettings;
+
+pub const HEADER_EVENT: &str = "X-Raffle-Event";
+pub const HEADER_DELIVERY: &str = "X-Raffle-Delivery";
+pub const HEADER_TIMESTAMP: &str = "X-Raffle-Timestamp";
+pub const HEADER_SIGNATURE: &str = "X-Raffle-Signature";
+
+/// How often and how fast a failed delivery is retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Delay before the second attempt, doubled for every further one.
+    pub base_delay: Duration,
+    pub timeout: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_settings(settings: &WebhookSettings) -> Self {
+        Self {
+            max_attempts: settings.max_attempts.max(1),
+            base_delay: Duration::from_secs(settings.retry_secs),
+            timeout: Duration::from_secs(settings.timeout_secs),
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay * 2u32.saturating_pow(attempt - 1)
+    }
+}
+
+/// Delivers every event published on `bus` to the webhooks subscribed to its type.
+pub fn spawn_dispatcher(db_interface: Arc<dyn RaffleRepository>, bus: &EventBus, policy: RetryPolicy) {
+    let mut events = bus.subscribe();
+    let http = reqwest::Client::builder()
+        .timeout(policy.timeout)
+        .build()
+        .expect("HTTP client can be built");
+    actix_web::rt::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Webhook dispatcher skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let webhooks = match db_interface.get_webhooks().await {
+                Ok(webhooks) => webhooks,
+                Err(err) => {
+                    warn!("Could not load webhooks for {}: {}", event.kind.as_str(), err);
+                    continue;
+                }
+            };
+            for webhook in webhooks {
+                if webhook.info.events.contains(&event.kind) {
+                    let (db_interface, http, event) = (db_interface.clone(), http.clone(), event.clone());
+                    actix_web::rt::spawn(async move {
+                        deliver(db_interface.as_ref(), &http, &webhook, &event, policy).await;
+                    });
+                }
+            }
+        }
+    });
+}
+
+/// POSTs `event` to `webhook` until it answers with a 2xx status or `policy` gives up,
+/// logging every attempt. Returns whether the event was delivered.
+pub async fn deliver(
+    db_interface: &dyn RaffleRepository,
+    http: &reqwest::Client,
+    webhook: &StoredWebhook,
+    event: &Event,
+    policy: RetryPolicy,
+) -> bool {
+    let body = serde_json::to_vec(event).unwrap_or_default();
+    for attempt in 1..=policy.max_attempts {
+        if attempt > 1 {
+            tokio::time::sleep(policy.delay(attempt - 1)).await;
+        }
+        let timestamp = chrono::Utc::now().timestamp();
+        let result = http
+            .post(&webhook.info.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(HEADER_EVENT, event.kind.as_str())
+            .header(HEADER_DELIVERY, event.id.to_hex())
+            .header(HEADER_TIMESTAMP, timestamp)
+            .header(HEADER_SIGNATURE, signature(&webhook.secret, timestamp, &body))
+            .body(body.clone())
+            .send()
+            .await;
+        let (status, error) = match result {
+            Ok(response) => (Some(response.status()), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        let success = status.is_some_and(|status| status.is_success());
+        let delivery = WebhookDelivery {
+            id: ObjectId::new(),
+            webhook_id: webhook.info.id,
+            event_id: event.id,
+            event: event.kind,
+            attempt,
+            status: status.map(|status| status.as_u16()),
+            error,
+            success,
+            date_created: timestamp,
+        };
+        if let Err(err) = db_interface.insert_webhook_delivery(&delivery).await {
+            warn!("Could not log webhook delivery {:?}: {}", delivery, err);
+        }
+        if success {
+            return true;
+        }
+    }
+    warn!(
+        "Giving up on {} {} for webhook {}",
+        event.kind.as_str(),
+        event.id,
+        webhook.info.id
+    );
+    false
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `<timestamp>.<body>` keyed with the webhook secret.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = NewWebhook,
+    responses(
+        (status = 201, description = "Webhook registered, the secret is only returned now", body = CreatedWebhook),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Missing `webhooks:admin` scope", body = ErrorBody),
+    ),
+)]
+#[post("/webhooks")]
+pub async fn create_webhook(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
+    form: web::Json<NewWebhook>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::WebhooksAdmin)?;
+    let form = form.into_inner();
+    let valid_url = reqwest::Url::parse(&form.url)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    ensure!(
+        valid_url,
+        InvalidRequestSnafu {
+            message: "url must be an http(s) URL"
+        }
+    );
+    ensure!(
+        !form.events.is_empty(),
+        InvalidRequestSnafu {
+            message: "events must not be empty"
+        }
+    );
+
+    let stored = StoredWebhook {
+        info: Webhook {
+            id: ObjectId::new(),
+            url: form.url,
+            events: form.events,
+            date_created: chrono::Utc::now().timestamp(),
+        },
+        secret: generate_secret(),
+    };
+    db_interface.insert_webhook(&stored).await?;
+    let info = Some(&stored.info);
+    audit::record(db_interface.as_ref(), &key, &req, "webhook", stored.info.id, None, info).await;
+    info!("Webhook {} for {} created by '{}'", stored.info.id, stored.info.url, key.name);
+    Ok(HttpResponse::Created().json(CreatedWebhook {
+        webhook: stored.info,
+        secret: stored.secret,
+    }))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "All webhooks, without secrets", body = Vec<Webhook>),
+        (status = 403, description = "Missing `webhooks:admin` scope", body = ErrorBody),
+    ),
+)]
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::WebhooksAdmin)?;
+    let webhooks: Vec<Webhook> = db_interface
+        .get_webhooks()
+        .await?
+        .into_iter()
+        .map(|webhook| webhook.info)
+        .collect();
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Webhook removed, its deliveries are kept", body = String, example = "ok"),
+        (status = 403, description = "Missing `webhooks:admin` scope", body = ErrorBody),
+        (status = 404, description = "No webhook with this id", body = ErrorBody),
+    ),
+)]
+#[delete("/webhooks/{id}")]
+pub async fn remove_webhook(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::WebhooksAdmin)?;
+    let id = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let before = db_interface
+        .get_webhooks()
+        .await?
+        .into_iter()
+        .find(|webhook| webhook.info.id == id)
+        .ok_or(ApiError::WebhookNotFound)?;
+    if db_interface.remove_webhook(id).await? == 0 {
+        return Err(ApiError::WebhookNotFound);
+    }
+    audit::record(db_interface.as_ref(), &key, &req, "webhook", id, Some(&before.info), None).await;
+    info!("Webhook {} removed by '{}'", id, key.name);
+    Ok(HttpResponse::Ok().body("ok"))
+}
+
+#[utoipa::path(
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id"), DeliveryQuery),
+    responses(
+        (status = 200, description = "Delivery attempts of the webhook", body = Page<WebhookDelivery>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 403, description = "Missing `webhooks:admin` scope", body = ErrorBody),
+    ),
+)]
+#[get("/webhooks/{id}/deliveries")]
+pub async fn list_deliveries(
+    key: ApiKey,
+    db_interface: web::Data<dyn RaffleRepository>,
+    id: web::Path<String>,
+    query: web::Query<DeliveryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    key.require(Scope::WebhooksAdmin)?;
+    let id = ObjectId::parse_str(id.into_inner()).context(InvalidIdSnafu)?;
+    let query = query.into_inner();
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(ObjectId::parse_str)
+        .transpose()
+        .context(InvalidIdSnafu)?;
+    let page = PageRequest::new(query.limit, cursor, query.sort);
+    Ok(HttpResponse::Ok().json(db_interface.find_webhook_deliveries(id, page).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use actix_web::{App, HttpRequest, HttpServer};
+
+    use super::*;
+    use crate::db_sql::DatabaseSql;
+
+    #[actix_web::test]
+    async fn deliveries_are_signed_retried_and_logged() {
+        let received = web::Data::new(Mutex::new(Vec::<(String, String, web::Bytes)>::new()));
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let receiver = {
+            let (received, calls) = (received.clone(), calls.clone());
+            HttpServer::new(move || {
+                App::new()
+                    .app_data(received.clone())
+                    .app_data(calls.clone())
+                    .route(
+                        "/hook",
+                        web::post().to(
+                            |req: HttpRequest,
+                             body: web::Bytes,
+                             received: web::Data<Mutex<Vec<(String, String, web::Bytes)>>>,
+                             calls: web::Data<AtomicUsize>| async move {
+                                let header = |name| {
+                                    req.headers().get(name).unwrap().to_str().unwrap().to_string()
+                                };
+                                received.lock().unwrap().push((
+                                    header(HEADER_TIMESTAMP),
+                                    header(HEADER_SIGNATURE),
+                                    body,
+                                ));
+                                // The first attempt fails
+                                if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                                    HttpResponse::ServiceUnavailable().finish()
+                                } else {
+                                    HttpResponse::Ok().finish()
+                                }
+                            },
+                        ),
+                    )
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap()
+        };
+        let address = receiver.addrs()[0];
+        actix_web::rt::spawn(receiver.run());
+
+        let db = DatabaseSql::connect("sqlite::memory:").await.unwrap();
+        let webhook = StoredWebhook {
+            info: Webhook {
+                id: ObjectId::new(),
+                url: format!("http://{}/hook", address),
+                events: vec![EventKind::TicketCreated],
+                date_created: 0,
+            },
+            secret: "secret".to_string(),
+        };
+        let event = Event {
+            id: ObjectId::new(),
+            kind: EventKind::TicketCreated,
+            raffle_id: ObjectId::new(),
+            data: serde_json::json!({"username": "alice"}),
+            date_created: 0,
+        };
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::ZERO,
+            timeout: Duration::from_secs(5),
+        };
+
+        assert!(deliver(&db, &reqwest::Client::new(), &webhook, &event, policy).await);
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+        for (timestamp, signature_header, body) in received.iter() {
+            let timestamp: i64 = timestamp.parse().unwrap();
+            assert_eq!(signature_header, &signature("secret", timestamp, body));
+            assert_eq!(serde_json::from_slice::<Event>(body).unwrap(), event);
+        }
+        let page = PageRequest::new(None, None, SortOrder::Asc);
+        let deliveries = db.find_webhook_deliveries(webhook.info.id, page).await.unwrap().items;
+        let attempts: Vec<_> = deliveries
+            .iter()
+            .map(|delivery| (delivery.attempt, delivery.status, delivery.success))
+            .collect();
+        assert_eq!(attempts, [(1, Some(503), false), (2, Some(200), true)]);
+    }
+}